@@ -0,0 +1,46 @@
+// Times the undo log against the old way of backing up, which copied every count and state at each choice point, with
+// the arena Program that patterns match with alongside. No benchmarking crate here, so it's just a stopwatch:
+// `cargo bench --features bench --bench backtracking`.
+
+use std::time::{Duration, Instant};
+
+use mregex_counter::regex::builder::Builder;
+use mregex_counter::regex::engine::Engine;
+use mregex_counter::regex::program::Program;
+
+const CASES: [(&str, &str); 5] = [
+    ("3N1\"-\"2N1\"-\"4N", "123-45-6789"),
+    ("1.20(1A,1N)", "a1b2c3d4e5f6g7h8i9j0"),
+    (".(1\"ab\",1N).3A", "abab12ab34abab56xyz"),
+    ("1.E1\"@\"1.E1\".\"2.3L", "someone.else@mail.example.org"),
+    ("1.(1U.L1\" \")1U.L", "Just Some Title Case Words Here Okay"),
+];
+
+fn time(states: &[mregex_counter::regex::rule::State], input: &str, snapshots: bool, rounds: u32) -> (Duration, bool) {
+    let start = Instant::now();
+    let mut matched = false;
+    for _ in 0..rounds {
+        let mut engine = Engine::new(input.to_string(), states.to_vec()).set_snapshot_backtracking(snapshots);
+        matched = engine.process().unwrap();
+    }
+    (start.elapsed() / rounds, matched)
+}
+
+fn main() {
+    let rounds = 200;
+    println!("{:<28} {:>14} {:>14} {:>8} {:>14}", "pattern", "snapshots", "undo log", "speedup", "program");
+    for (text, input) in CASES {
+        let pattern = Builder::init_default(text.to_string()).build().unwrap();
+        let (old, old_match) = time(pattern.get_states(), input, true, rounds);
+        let (new, new_match) = time(pattern.get_states(), input, false, rounds);
+        assert_eq!(old_match, new_match, "the two strategies disagree on {}", text);
+
+        let program = Program::compile(pattern.get_states());
+        let start = Instant::now();
+        for _ in 0..rounds {
+            assert_eq!(program.is_match(input), new_match);
+        }
+        let arena = start.elapsed() / rounds;
+        println!("{:<28} {:>14?} {:>14?} {:>7.1}x {:>14?}", text, old, new, old.as_secs_f64() / new.as_secs_f64(), arena);
+    }
+}
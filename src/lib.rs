@@ -1,5 +1,7 @@
 pub mod regex;
 
 // Some constants
-pub const BLOCK_TRUE_UPPER_LIM: usize = 1000;
-pub const DEFAULT_ENGINE_TYPICAL_MAX: usize = 10;
\ No newline at end of file
+pub const DEFAULT_ENGINE_TYPICAL_MAX: usize = 10;
+
+/// What everything that needs at least one state says when there are none.
+pub const NO_STATES_ERROR: &str = "No valid states are found to fulfill. Exiting.";
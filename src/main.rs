@@ -1,10 +1,113 @@
-use mregex_counter::regex::engine::*;
-use mregex_counter::regex::rule::*;
-
-fn main() {
-    let block_states = vec![State::new(1, Some(1), vec![PatternType::Alphabetic]), State::new(1, Some(1), vec![PatternType::Numeric])];
-    let block_state = State::new_coalesce_block(1, Some(4), block_states);
-    let mut engine = Engine::new("123".to_string(), vec![block_state]);
-    engine.process();
-    println!("{:#?}", engine.extract_results());
+use std::process::ExitCode;
+
+use mregex_counter::regex::builder::Builder;
+use mregex_counter::regex::format::canonicalize_with;
+
+const USAGE: &str = "usage: mregex_counter fmt [--check] [--extended] <file>...
+       mregex_counter explain [--extended] <pattern>...
+
+fmt      Prints the canonical form of every pattern in the files, one pattern per line.
+         With --check, prints nothing but the lines that aren't canonical, and fails if there are any.
+explain  Describes each pattern in plain English, with a blank line between them.";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.split_first() {
+        Some((command, rest)) if command == "fmt" => format_files(rest),
+        Some((command, rest)) if command == "explain" => explain_patterns(rest),
+        _ => {
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Splits the arguments into the flags given and everything else. Anything starting with -- that isn't one of the known
+/// flags gets printed along with the usage, so a typo doesn't get treated as a file or a pattern.
+fn split_flags<'a>(args: &'a [String], known: &[&str]) -> Option<(Vec<&'a str>, Vec<&'a String>)> {
+    let (flags, rest): (Vec<&String>, Vec<&String>) = args.iter().partition(|a| a.starts_with("--"));
+    if let Some(unknown) = flags.iter().find(|flag| !known.contains(&flag.as_str())) {
+        eprintln!("unknown option: {}\n{}", unknown, USAGE);
+        return None;
+    }
+    if rest.is_empty() {
+        eprintln!("{}", USAGE);
+        return None;
+    }
+    Some((flags.into_iter().map(|flag| flag.as_str()).collect(), rest))
+}
+
+fn format_files(args: &[String]) -> ExitCode {
+    let Some((flags, files)) = split_flags(args, &["--check", "--extended"]) else {
+        return ExitCode::FAILURE;
+    };
+    let check = flags.contains(&"--check");
+    let extended = flags.contains(&"--extended");
+
+    let mut clean = true;
+    for file in files {
+        let text = match std::fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                clean = false;
+                continue;
+            }
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let pattern = line.trim();
+            if pattern.is_empty() {
+                continue;
+            }
+
+            let builder = Builder::init_default(pattern.to_string()).set_extended_syntax(extended);
+            match canonicalize_with(&builder) {
+                Err(e) => {
+                    eprintln!("{}:{}: {}: {}", file, number + 1, pattern, e);
+                    clean = false;
+                }
+                Ok(canonical) if check => {
+                    if canonical != pattern {
+                        println!("{}:{}: {} should be {}", file, number + 1, pattern, canonical);
+                        clean = false;
+                    }
+                }
+                Ok(canonical) => println!("{}", canonical),
+            }
+        }
+    }
+
+    if clean {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn explain_patterns(args: &[String]) -> ExitCode {
+    let Some((flags, patterns)) = split_flags(args, &["--extended"]) else {
+        return ExitCode::FAILURE;
+    };
+    let extended = flags.contains(&"--extended");
+
+    let mut clean = true;
+    for (number, pattern) in patterns.into_iter().enumerate() {
+        if number > 0 {
+            println!();
+        }
+        match Builder::init_default(pattern.to_string()).set_extended_syntax(extended).build() {
+            Ok(compiled) => println!("{}", compiled.explain()),
+            Err(e) => {
+                eprintln!("{}: {}", pattern, e);
+                clean = false;
+            }
+        }
+    }
+
+    if clean {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
@@ -0,0 +1,303 @@
+use std::collections::BTreeSet;
+
+use super::helpers::case_variants;
+use super::rule::{Classification, PatternType, State};
+
+/// The code classes cut up so none of them overlap, going by ASCII mode. A is the three kinds of letter together, and E
+/// is all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CharClass {
+    Upper,
+    Lower,
+    OtherLetter, // Letters with no case, which ASCII doesn't have but a bigger A can make room for.
+    Numeric,
+    Punctuation,
+    Control,
+    Other, // Only E, so anything past ASCII.
+}
+
+impl CharClass {
+    pub const ALL: [CharClass; 7] = [Self::Upper, Self::Lower, Self::OtherLetter, Self::Numeric, Self::Punctuation, Self::Control, Self::Other];
+
+    /// Which class a character lands in under ASCII mode.
+    pub fn of(character: char) -> Self {
+        let is = |pattern: PatternType| pattern.is_of_type_in(character, Classification::Ascii);
+        if is(PatternType::Uppercase) {
+            Self::Upper
+        } else if is(PatternType::Lowercase) {
+            Self::Lower
+        } else if is(PatternType::Numeric) {
+            Self::Numeric
+        } else if is(PatternType::Punctuation) {
+            Self::Punctuation
+        } else if is(PatternType::Control) {
+            Self::Control
+        } else {
+            Self::Other
+        }
+    }
+
+    /// Whether a character of this class fits the pattern type, going by the codes alone. Anything that names
+    /// characters, like ranges or user-defined codes, never covers a whole class.
+    fn fits(self, pattern: &PatternType) -> bool {
+        match pattern {
+            PatternType::Everything => true,
+            PatternType::Alphabetic => matches!(self, Self::Upper | Self::Lower | Self::OtherLetter),
+            PatternType::Uppercase => self == Self::Upper,
+            PatternType::Lowercase => self == Self::Lower,
+            PatternType::Numeric => self == Self::Numeric,
+            PatternType::Punctuation => self == Self::Punctuation,
+            PatternType::Control => self == Self::Control,
+            PatternType::Not(pattern) => !self.fits(pattern),
+            PatternType::AnyOf(patterns) => patterns.iter().any(|p| self.fits(p)),
+            _ => false,
+        }
+    }
+
+    /// Something to show for a character of the class that the pattern doesn't name, when every ASCII one is taken.
+    fn fallback(self) -> char {
+        match self {
+            Self::Upper => '\u{c0}',
+            Self::Lower => '\u{e0}',
+            Self::OtherLetter => '\u{aa}',
+            Self::Numeric => '\u{663}',
+            Self::Punctuation => '\u{a1}',
+            Self::Control => '\u{80}',
+            Self::Other => '\u{100}',
+        }
+    }
+}
+
+/// How many characters each code class holds, for counting the strings a pattern accepts. Starts off as plain ASCII.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClassSizes {
+    alphabetic: u64,
+    upper: u64,
+    lower: u64,
+    numeric: u64,
+    punctuation: u64,
+    control: u64,
+    everything: u64,
+}
+
+impl Default for ClassSizes {
+    fn default() -> Self {
+        Self { alphabetic: 52, upper: 26, lower: 26, numeric: 10, punctuation: 33, control: 33, everything: 128 }
+    }
+}
+
+impl ClassSizes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A can be bigger than U and L together, the rest are letters without case.
+    pub fn set_alphabetic(mut self, size: u64) -> Self {
+        self.alphabetic = size;
+        self
+    }
+
+    pub fn set_upper(mut self, size: u64) -> Self {
+        self.upper = size;
+        self
+    }
+
+    pub fn set_lower(mut self, size: u64) -> Self {
+        self.lower = size;
+        self
+    }
+
+    pub fn set_numeric(mut self, size: u64) -> Self {
+        self.numeric = size;
+        self
+    }
+
+    pub fn set_punctuation(mut self, size: u64) -> Self {
+        self.punctuation = size;
+        self
+    }
+
+    pub fn set_control(mut self, size: u64) -> Self {
+        self.control = size;
+        self
+    }
+
+    /// E has to hold everything else, anything left over only fits E.
+    pub fn set_everything(mut self, size: u64) -> Self {
+        self.everything = size;
+        self
+    }
+
+    /// How many characters are in one of the non-overlapping classes.
+    pub fn size_of(&self, class: CharClass) -> Result<u64, &'static str> {
+        match class {
+            CharClass::Upper => Ok(self.upper),
+            CharClass::Lower => Ok(self.lower),
+            CharClass::OtherLetter => {
+                let cased = self.upper.checked_add(self.lower);
+                cased.and_then(|cased| self.alphabetic.checked_sub(cased)).ok_or("A can't be smaller than U and L together.")
+            }
+            CharClass::Numeric => Ok(self.numeric),
+            CharClass::Punctuation => Ok(self.punctuation),
+            CharClass::Control => Ok(self.control),
+            CharClass::Other => {
+                let named = [self.numeric, self.punctuation, self.control].into_iter().try_fold(self.alphabetic, u64::checked_add);
+                named.and_then(|named| self.everything.checked_sub(named)).ok_or("E can't be smaller than the other classes together.")
+            }
+        }
+    }
+}
+
+/// A group of characters no state can tell apart, shown as one of them. Either it's a single character, or it's every
+/// character of a class that the states never name, `weight` of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    character: char,
+    class: Option<CharClass>,
+    weight: u64,
+}
+
+impl Symbol {
+    pub fn get_character(&self) -> char {
+        self.character
+    }
+
+    /// How many characters the symbol stands for.
+    pub fn get_weight(&self) -> u64 {
+        self.weight
+    }
+
+    /// Whether the symbol stands for a whole class rather than one character.
+    pub fn get_class(&self) -> Option<CharClass> {
+        self.class
+    }
+
+    /// Whether a plain state takes the symbol. Literals get checked a character at a time by whoever's asking.
+    pub fn fits(&self, state: &State) -> bool {
+        match self.class {
+            None => state.does_char_qualify(self.character),
+            Some(class) => state.get_patterns().iter().any(|p| class.fits(p)),
+        }
+    }
+}
+
+/// The characters strings get made of, as far as counting, listing and comparing patterns goes. Sorted by the
+/// character each symbol is shown as.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Alphabet {
+    symbols: Vec<Symbol>,
+}
+
+impl Alphabet {
+    /// Exactly these characters and nothing else.
+    pub fn from_chars<I: IntoIterator<Item = char>>(characters: I) -> Self {
+        let characters: BTreeSet<char> = characters.into_iter().collect();
+        Self { symbols: characters.into_iter().map(|character| Symbol { character, class: None, weight: 1 }).collect() }
+    }
+
+    /// Every character the states name gets a symbol of its own, and whatever's left of each class is one more. The
+    /// states are read in ASCII mode, so ranges, sets, user-defined codes and predicates only name ASCII characters.
+    pub fn for_states(states: &[State], sizes: &ClassSizes) -> Result<Self, &'static str> {
+        let mut named = BTreeSet::new();
+        for state in states {
+            collect_named(state, &mut named);
+        }
+
+        let mut symbols: Vec<Symbol> = named.iter().map(|character| Symbol { character: *character, class: None, weight: 1 }).collect();
+        for class in CharClass::ALL {
+            let taken = named.iter().filter(|c| CharClass::of(**c) == class).count() as u64;
+            let weight = sizes.size_of(class)?.checked_sub(taken).ok_or("The class sizes don't leave room for the characters the pattern names.")?;
+            if weight > 0 {
+                let character = ['-', '\t'].into_iter().chain((0..128u8).map(char::from)).find(|c| CharClass::of(*c) == class && !named.contains(c)).unwrap_or(class.fallback());
+                symbols.push(Symbol { character, class: Some(class), weight });
+            }
+        }
+        symbols.sort_by_key(|symbol| symbol.character);
+        Ok(Self { symbols })
+    }
+
+    /// Same as for_states, but for when only telling characters apart matters and not how many there are. E is as big
+    /// as it needs to be, so there's always room.
+    pub fn separating(states: &[State]) -> Self {
+        Self::for_states(states, &ClassSizes::new().set_everything(u64::MAX)).expect("the standard classes fit whatever ASCII the states name")
+    }
+
+    pub fn get_symbols(&self) -> &Vec<Symbol> {
+        &self.symbols
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Which symbol a character belongs to, if it's in the alphabet at all.
+    pub fn symbol_of(&self, character: char) -> Option<usize> {
+        self.symbols.iter().position(|s| s.class.is_none() && s.character == character).or_else(|| {
+            let class = CharClass::of(character);
+            self.symbols.iter().position(|s| s.class == Some(class))
+        })
+    }
+}
+
+/// Adds the characters a state and everything in it single out, as opposed to taking a whole class.
+fn collect_named(state: &State, named: &mut BTreeSet<char>) {
+    if let Some(states) = state.get_block_states() {
+        states.iter().for_each(|s| collect_named(s, named));
+        return;
+    }
+
+    let mut found = BTreeSet::new();
+    for pattern in state.get_patterns() {
+        collect_named_in(pattern, state, &mut found);
+    }
+    if state.is_case_insensitive() {
+        found = found.into_iter().flat_map(case_variants).collect();
+    }
+    named.extend(found);
+}
+
+fn collect_named_in(pattern: &PatternType, state: &State, found: &mut BTreeSet<char>) {
+    let ascii = (0..128u8).map(char::from);
+    match pattern {
+        PatternType::Specific(c) => {
+            found.insert(*c);
+        }
+        PatternType::Literal(text) => found.extend(text.chars()),
+        PatternType::Not(pattern) => collect_named_in(pattern, state, found),
+        PatternType::AnyOf(patterns) => patterns.iter().for_each(|p| collect_named_in(p, state, found)),
+        PatternType::Range(..) | PatternType::Set(_) | PatternType::Custom(_) | PatternType::Predicate(_) => {
+            found.extend(ascii.filter(|c| pattern.is_of_type_with(*c, Classification::Ascii, state.get_pattern_table())));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_characters_get_their_own_symbols() {
+        let states = vec![State::new(2, Some(2), vec![PatternType::Uppercase]), State::new_literal(1, Some(1), "-"), State::new(1, Some(1), vec![PatternType::Range('0', '4')])];
+        let alphabet = Alphabet::for_states(&states, &ClassSizes::new()).unwrap();
+        // '-', 0-4, and one each for the rest of U, L, N, P and C.
+        assert_eq!(alphabet.len(), 1 + 5 + 5);
+        assert_eq!(alphabet.get_symbols().iter().map(|s| s.get_weight()).sum::<u64>(), 128);
+        assert_eq!(alphabet.symbol_of('7'), alphabet.symbol_of('9'));
+        assert_ne!(alphabet.symbol_of('3'), alphabet.symbol_of('9'));
+        let punctuation = &alphabet.get_symbols()[alphabet.symbol_of('!').unwrap()];
+        assert_eq!((punctuation.get_class(), punctuation.get_weight()), (Some(CharClass::Punctuation), 32));
+
+        let sizes = ClassSizes::new().set_numeric(3).set_everything(121);
+        assert!(Alphabet::for_states(&states, &sizes).is_err());
+        assert!(Alphabet::for_states(&states, &ClassSizes::new().set_alphabetic(50)).is_err());
+
+        // Sizes that don't fit in a u64 once added up are just as wrong.
+        assert!(ClassSizes::new().set_upper(u64::MAX).size_of(CharClass::OtherLetter).is_err());
+        assert!(ClassSizes::new().set_alphabetic(u64::MAX).set_everything(u64::MAX).size_of(CharClass::Other).is_err());
+    }
+}
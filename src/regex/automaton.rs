@@ -0,0 +1,396 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::alphabet::{Alphabet, CharClass};
+use super::helpers::case_variants;
+use super::natural::Natural;
+use super::rule::State;
+
+// Patterns as finite automata over an Alphabet, for the questions backtracking can't answer: how many strings a pattern
+// takes, which ones, and how two patterns compare. The states get unrolled into a nondeterministic automaton, with one
+// copy of a repeated state per repetition up to the max, and then turned deterministic one alphabet symbol at a time.
+
+/// What it takes to follow a transition: a plain state's classes, or one character of a literal.
+enum Test {
+    Leaf(State),
+    Char(char, bool),
+}
+
+#[derive(Default)]
+struct Nfa {
+    tests: Vec<Test>,
+    empty: Vec<Vec<usize>>, // Transitions that don't take a character.
+    steps: Vec<Vec<(usize, usize)>>, // Test to pass, and where it goes.
+}
+
+impl Nfa {
+    fn node(&mut self) -> usize {
+        self.empty.push(Vec::new());
+        self.steps.push(Vec::new());
+        self.empty.len() - 1
+    }
+
+    fn step(&mut self, from: usize, test: Test) -> usize {
+        let to = self.node();
+        self.tests.push(test);
+        self.steps[from].push((self.tests.len() - 1, to));
+        to
+    }
+
+    /// Builds the state with all its repetitions, starting from `from`, and gives back where it ends up.
+    fn piece(&mut self, state: &State, from: usize) -> usize {
+        let (min, max) = (state.get_min(), state.get_max());
+        match state.get_block_states() {
+            // Every repetition of a coalesce is the same option.
+            Some(options) if state.is_coalesce() => {
+                let end = self.node();
+                if min == 0 {
+                    self.empty[from].push(end);
+                }
+                for option in options {
+                    let finish = self.repeated(min, max, from, &mut |nfa, from| nfa.piece(option, from));
+                    self.empty[finish].push(end);
+                }
+                end
+            }
+            Some(states) => self.repeated(min, max, from, &mut |nfa, from| states.iter().fold(from, |at, s| nfa.piece(s, at))),
+            None => match state.get_literal() {
+                Some(text) => {
+                    let fold_case = state.is_case_insensitive();
+                    self.repeated(min, max, from, &mut |nfa, from| text.chars().fold(from, |at, c| nfa.step(at, Test::Char(c, fold_case))))
+                }
+                None => self.repeated(min, max, from, &mut |nfa, from| nfa.step(from, Test::Leaf(state.clone()))),
+            },
+        }
+    }
+
+    /// `unit` repeated between `min` and `max` times, a fresh copy for each one.
+    fn repeated(&mut self, min: usize, max: Option<usize>, from: usize, unit: &mut dyn FnMut(&mut Self, usize) -> usize) -> usize {
+        let mut at = from;
+        for _ in 0..min {
+            at = unit(self, at);
+        }
+        match max {
+            None => {
+                let again = self.node();
+                self.empty[at].push(again);
+                let back = unit(self, again);
+                self.empty[back].push(again);
+                again
+            }
+            Some(max) => {
+                let end = self.node();
+                for _ in min..max {
+                    self.empty[at].push(end);
+                    at = unit(self, at);
+                }
+                self.empty[at].push(end);
+                end
+            }
+        }
+    }
+
+    /// Everything reachable without taking a character, sorted so it can be used as a key.
+    fn closure(&self, nodes: &[usize]) -> Vec<usize> {
+        let mut seen = vec![false; self.empty.len()];
+        let mut pending = nodes.to_vec();
+        let mut found = Vec::new();
+        while let Some(node) = pending.pop() {
+            if !std::mem::replace(&mut seen[node], true) {
+                found.push(node);
+                pending.extend(&self.empty[node]);
+            }
+        }
+        found.sort_unstable();
+        found
+    }
+}
+
+/// A deterministic automaton over an alphabet, with a transition for every symbol out of every state. State 0 is the
+/// start.
+#[derive(Clone, Debug)]
+pub struct Dfa {
+    alphabet: Alphabet,
+    transitions: Vec<Vec<usize>>,
+    accepting: Vec<bool>,
+}
+
+impl Dfa {
+    /// The automaton for the states one after the other, which is the whole pattern.
+    pub fn compile(states: &[State], alphabet: Alphabet) -> Self {
+        let mut nfa = Nfa::default();
+        let start = nfa.node();
+        let accept = states.iter().fold(start, |at, state| nfa.piece(state, at));
+
+        // Which tests each symbol passes, worked out once up front.
+        let passes: Vec<Vec<bool>> = alphabet.get_symbols().iter().map(|symbol| {
+            nfa.tests.iter().map(|test| match test {
+                Test::Leaf(state) => symbol.fits(state),
+                Test::Char(c, fold_case) => {
+                    symbol.get_class().is_none() && (symbol.get_character() == *c || (*fold_case && case_variants(*c).contains(&symbol.get_character())))
+                }
+            }).collect()
+        }).collect();
+
+        let first = nfa.closure(&[start]);
+        let mut seen = HashMap::from([(first.clone(), 0)]);
+        let mut sets = vec![first];
+        let mut transitions = Vec::new();
+        while transitions.len() < sets.len() {
+            let set = sets[transitions.len()].clone();
+            let mut row = Vec::with_capacity(alphabet.len());
+            for passing in &passes {
+                let next: Vec<usize> = set.iter().flat_map(|node| &nfa.steps[*node]).filter(|(test, _)| passing[*test]).map(|(_, to)| *to).collect();
+                let next = nfa.closure(&next);
+                let id = *seen.entry(next.clone()).or_insert_with(|| {
+                    sets.push(next);
+                    sets.len() - 1
+                });
+                row.push(id);
+            }
+            transitions.push(row);
+        }
+
+        let accepting = sets.iter().map(|set| set.binary_search(&accept).is_ok()).collect();
+        Self { alphabet, transitions, accepting }
+    }
+
+    /// Takes exactly the strings over the alphabet this one doesn't.
+    pub fn complement(mut self) -> Self {
+        self.accepting.iter_mut().for_each(|accepting| *accepting = !*accepting);
+        self
+    }
+
+    /// Runs both automata side by side, accepting wherever `keep` says to given whether each of them would. They have
+    /// to share an alphabet.
+    pub fn product(&self, other: &Dfa, keep: impl Fn(bool, bool) -> bool) -> Dfa {
+        debug_assert_eq!(self.alphabet, other.alphabet);
+        let mut seen = HashMap::from([((0, 0), 0)]);
+        let mut pairs = vec![(0, 0)];
+        let mut transitions = Vec::new();
+        while transitions.len() < pairs.len() {
+            let (a, b) = pairs[transitions.len()];
+            let row = self.transitions[a].iter().zip(&other.transitions[b]).map(|pair| {
+                let pair = (*pair.0, *pair.1);
+                *seen.entry(pair).or_insert_with(|| {
+                    pairs.push(pair);
+                    pairs.len() - 1
+                })
+            }).collect();
+            transitions.push(row);
+        }
+
+        let accepting = pairs.iter().map(|(a, b)| keep(self.accepting[*a], other.accepting[*b])).collect();
+        Dfa { alphabet: self.alphabet.clone(), transitions, accepting }
+    }
+
+    pub fn get_alphabet(&self) -> &Alphabet {
+        &self.alphabet
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.transitions.len()
+    }
+
+    /// Runs the automaton over the input. Characters outside the alphabet never match.
+    pub fn accepts(&self, input: &str) -> bool {
+        let mut at = 0;
+        for character in input.chars() {
+            match self.alphabet.symbol_of(character) {
+                Some(symbol) => at = self.transitions[at][symbol],
+                None => return false,
+            }
+        }
+        self.accepting[at]
+    }
+
+    /// How many strings of each length up to `max_len` it takes, counting a symbol as every character it stands for.
+    pub fn count_by_length(&self, max_len: usize) -> Vec<Natural> {
+        let weights: Vec<u64> = self.alphabet.get_symbols().iter().map(|s| s.get_weight()).collect();
+        let mut ways = vec![Natural::zero(); self.state_count()];
+        ways[0] = Natural::one();
+        let mut counts = Vec::with_capacity(max_len + 1);
+        for len in 0..=max_len {
+            let mut total = Natural::zero();
+            for (state, count) in ways.iter().enumerate() {
+                if self.accepting[state] {
+                    total.add_assign(count);
+                }
+            }
+            counts.push(total);
+            if len == max_len {
+                break;
+            }
+
+            let mut next = vec![Natural::zero(); self.state_count()];
+            for (state, count) in ways.iter().enumerate().filter(|(_, count)| !count.is_zero()) {
+                for (symbol, to) in self.transitions[state].iter().enumerate() {
+                    next[*to].add_assign(&count.mul_small(weights[symbol]));
+                }
+            }
+            ways = next;
+        }
+        counts
+    }
+
+    /// One of the shortest strings it takes, if it takes any. Out of those, it goes for letters over digits over
+    /// punctuation, so it reads about as well as it can.
+    pub fn shortest(&self) -> Option<String> {
+        let symbols = self.alphabet.get_symbols();
+        let mut order: Vec<usize> = (0..symbols.len()).collect();
+        order.sort_by_key(|symbol| (CharClass::of(symbols[*symbol].get_character()), symbols[*symbol].get_character()));
+
+        // Breadth first, remembering how each state was first reached.
+        let mut came_from: Vec<Option<(usize, usize)>> = vec![None; self.state_count()];
+        let mut seen = vec![false; self.state_count()];
+        let mut pending = VecDeque::from([0]);
+        seen[0] = true;
+        while let Some(state) = pending.pop_front() {
+            if self.accepting[state] {
+                let mut text = Vec::new();
+                let mut at = state;
+                while let Some((from, symbol)) = came_from[at] {
+                    text.push(symbols[symbol].get_character());
+                    at = from;
+                }
+                return Some(text.into_iter().rev().collect());
+            }
+            for symbol in &order {
+                let to = self.transitions[state][*symbol];
+                if !std::mem::replace(&mut seen[to], true) {
+                    came_from[to] = Some((state, *symbol));
+                    pending.push_back(to);
+                }
+            }
+        }
+        None
+    }
+
+    /// Every string it takes up to `max_len` characters, shortest first and then in alphabetical order. Symbols that
+    /// stand for a whole class come out as the one character they're shown as.
+    pub fn strings(self, max_len: usize) -> Strings {
+        // Whether each state can still get to accepting in exactly so many more characters.
+        let mut reach = vec![self.accepting.clone()];
+        for steps in 1..=max_len {
+            let row = self.transitions.iter().map(|row| row.iter().any(|to| reach[steps - 1][*to])).collect();
+            reach.push(row);
+        }
+        Strings { dfa: self, reach, max_len, len: 0, fresh: true, stack: Vec::new(), text: Vec::new() }
+    }
+}
+
+/// Walks the automaton depth first one length at a time, only going down paths that can still finish at that length.
+pub struct Strings {
+    dfa: Dfa,
+    reach: Vec<Vec<bool>>,
+    max_len: usize,
+    len: usize,
+    fresh: bool, // Nothing's been tried at this length yet.
+    stack: Vec<(usize, usize)>, // Automaton state, and the next symbol to try out of it.
+    text: Vec<char>,
+}
+
+impl Iterator for Strings {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if self.fresh {
+                if self.len > self.max_len {
+                    return None;
+                }
+                self.fresh = false;
+                if self.reach[self.len][0] {
+                    self.stack.push((0, 0));
+                }
+            }
+
+            let depth = self.text.len();
+            let Some((state, next)) = self.stack.last_mut() else {
+                self.len += 1;
+                self.fresh = true;
+                continue;
+            };
+
+            if depth == self.len {
+                let found = self.text.iter().collect();
+                self.stack.pop();
+                self.text.pop();
+                return Some(found);
+            }
+
+            let targets = &self.dfa.transitions[*state];
+            let left = self.len - depth - 1;
+            match (*next..targets.len()).find(|symbol| self.reach[left][targets[*symbol]]) {
+                Some(symbol) => {
+                    *next = symbol + 1;
+                    self.stack.push((targets[symbol], 0));
+                    self.text.push(self.dfa.alphabet.get_symbols()[symbol].get_character());
+                }
+                None => {
+                    self.stack.pop();
+                    self.text.pop();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::alphabet::ClassSizes;
+    use crate::regex::program::Program;
+    use crate::regex::testing;
+
+    fn compile(text: &str) -> Dfa {
+        let states = testing::compile(text).get_states().clone();
+        Dfa::compile(&states, Alphabet::for_states(&states, &ClassSizes::new()).unwrap())
+    }
+
+    #[test]
+    fn agrees_with_the_program() {
+        let texts = ["3N1\"-\"2N1\"-\"4N", "1.3(1A,1N1\".\")", ".(2N,1\"x\"1A).E", "2(1\"ab\"i,1N)1.2(.2A1N)", "1(2N,3A)", "1U.L", ".N.E1(1\"a\",1\"ab\").E"];
+        let inputs = ["", "1", "123-45-6789", "a1.b", "x12xA...", "AB12a1", "AB", "1.1.1.", "ab7a1b2", "Abc", "12345", "12abc"];
+        for text in texts {
+            let dfa = compile(text);
+            let pattern = testing::compile(text);
+            for input in inputs {
+                assert_eq!(dfa.accepts(input), pattern.is_match(input).unwrap(), "{} on {:?}", text, input);
+            }
+        }
+    }
+
+    #[test]
+    fn counts_strings_by_length() {
+        let counts = compile("2U4N").count_by_length(7);
+        assert_eq!(counts[6].to_u128(), Some(26 * 26 * 10_000));
+        assert!(counts.iter().enumerate().all(|(len, count)| len == 6 || count.is_zero()));
+
+        // Ambiguous parses still only count each string once.
+        let counts = compile(".N.N").count_by_length(3);
+        assert_eq!(counts.iter().map(|c| c.to_u128().unwrap()).collect::<Vec<u128>>(), vec![1, 10, 100, 1000]);
+        let counts = compile("1(1\"Y\",1\"N\")1.2N").count_by_length(3);
+        assert_eq!(counts.iter().map(|c| c.to_u128().unwrap()).collect::<Vec<u128>>(), vec![0, 0, 20, 200]);
+    }
+
+    #[test]
+    fn lists_strings_in_shortlex_order() {
+        let characters = ['a', 'B', '1', '2', '-'];
+        for text in ["1(1\"B\",1\"a\")1.2N", ".N.E", "1.3(1A,1N1\"-\")", "2(1\"ab\"i,1N)"] {
+            let states = testing::compile(text).get_states().clone();
+            let listed: Vec<String> = Dfa::compile(&states, Alphabet::from_chars(characters)).strings(4).collect();
+
+            // Every string over the alphabet, shortest first, that the program matches.
+            let mut expected = vec![String::new()];
+            let mut sorted = characters;
+            sorted.sort();
+            for len in 1..=4 {
+                let longer: Vec<String> = expected.iter().filter(|s| s.chars().count() == len - 1).flat_map(|s| sorted.iter().map(move |c| format!("{}{}", s, c))).collect();
+                expected.extend(longer);
+            }
+            let program = Program::compile(&states);
+            expected.retain(|s| program.is_match(s));
+            assert_eq!(listed, expected, "{}", text);
+        }
+    }
+}
@@ -1,7 +1,12 @@
+use std::sync::Arc;
+
 use crate::DEFAULT_ENGINE_TYPICAL_MAX;
 
+use super::dialect::Dialect;
 use super::engine::*;
+use super::pattern::Pattern;
 use super::rule::{PatternType, State};
+use super::table::PatternTable;
 
 
 // Basic string parser that builds the rule states for use with the engine. Hard-coded version of regex that allows custom regex build.
@@ -10,6 +15,10 @@ pub struct Builder {
     input_string: String,
     engine: Engine, // Note that this is not the actual engine that gets outputted. The builder is supposed to output an engine.
     interpreted_substrings: Vec<(State, String)>,
+    dialect: Dialect,
+    table: Option<Arc<PatternTable>>,
+    extended: bool,
+    fold_case: bool,
 }
 
 impl Builder {
@@ -20,7 +29,7 @@ impl Builder {
 
     /// Processes the builder provided that there is a valid engine (should always at least have the default) and 
     pub fn process(&mut self) -> Result<bool, &'static str> {
-        if self.input_string.len() == 0 {
+        if self.input_string.is_empty() {
             return Err("Input string is null, fatal error.");
         }
         if !self.engine.process()? {
@@ -36,12 +45,65 @@ impl Builder {
         }
     }
 
+    /// Picks which M implementation's rules the pattern text follows. Defaults to plain ANSI M.
+    pub fn set_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Loads user-defined pattern codes. Only dialects with user codes (GT.M) look letters up in here, so building
+    /// with a table under any other dialect is an error.
+    pub fn set_pattern_table(mut self, table: PatternTable) -> Self {
+        self.table = Some(Arc::new(table));
+        self
+    }
+
+    /// Turns on syntax that isn't M at all: `[A-F0-9]` character sets, `[^...]` for anything but the set, and `'` in
+    /// front of any code group regardless of dialect.
+    pub fn set_extended_syntax(mut self, extended: bool) -> Self {
+        self.extended = extended;
+        self
+    }
+
+    /// Makes the compiled pattern ignore case for literals and specific characters (and sets, with extended syntax).
+    /// The U and L codes still care. With extended syntax a single literal can opt in with a trailing `i`: `1"yes"i`.
+    pub fn set_case_insensitive(mut self, fold_case: bool) -> Self {
+        self.fold_case = fold_case;
+        self
+    }
+
+    /// Compiles the pattern text into a Pattern. A leading `'` negates the whole pattern, the same way `X'?1N` does in M.
+    pub fn build(&self) -> Result<Pattern, &'static str> {
+        if self.table.is_some() && !self.dialect.allows_user_codes() {
+            return Err("Pattern tables only apply to dialects with user-defined codes.");
+        }
+        let mut parser = Parser::new(&self.input_string, self.dialect, self.table.clone(), self.extended);
+        let negated = parser.eat('\'');
+        let states = parser.parse_sequence()?;
+        if !parser.is_done() {
+            return Err("Unexpected character in pattern.");
+        }
+
+        let mut pattern = Pattern::new(states).set_classification(self.dialect.classification());
+        if let Some(table) = &self.table {
+            pattern = pattern.set_pattern_table(table.clone());
+        }
+        if self.fold_case {
+            pattern = pattern.set_case_insensitive(true);
+        }
+        Ok(if negated { pattern.negate() } else { pattern })
+    }
+
     /// I used the engine to create the engine.
     fn new(input_string: String, engine: Engine) -> Self {
         Self {
             input_string,
             engine,
             interpreted_substrings: Vec::new(),
+            dialect: Dialect::default(),
+            table: None,
+            extended: false,
+            fold_case: false,
         }
     }
 
@@ -83,6 +145,7 @@ impl Builder {
 
     }
 
+    #[allow(dead_code, clippy::vec_init_then_push)] // The first take on default_engine_rebuild, kept for reference.
     fn default_engine(input_string: String) -> Engine {
         let mut core_block = Vec::new(); // This needs to make it into a final state that repeats
         core_block.push(State::new(0, Some(DEFAULT_ENGINE_TYPICAL_MAX), vec![PatternType::Numeric]).set_identifier(1)); // First a number, is optional
@@ -136,7 +199,236 @@ impl Builder {
     }
 }
 
+/// Hand written recursive descent over the pattern text. The engine above can tell us whether something looks like a
+/// pattern, but this is what actually turns it into states.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    next_identifier: usize, // Every atom gets its own identifier, in the order they show up in the text.
+    dialect: Dialect,
+    table: Option<Arc<PatternTable>>,
+    extended: bool,
+}
+
+impl Parser {
+    fn new(text: &str, dialect: Dialect, table: Option<Arc<PatternTable>>, extended: bool) -> Self {
+        Self {
+            chars: text.chars().collect(),
+            pos: 0,
+            next_identifier: 1,
+            dialect,
+            table,
+            extended,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    fn eat(&mut self, character: char) -> bool {
+        if self.peek() == Some(character) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// One or more atoms, up until the end of the text, a comma, or a closing parenthesis.
+    fn parse_sequence(&mut self) -> Result<Vec<State>, &'static str> {
+        let mut states = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == ',' || c == ')' {
+                break;
+            }
+            states.push(self.parse_atom()?);
+        }
+
+        if states.is_empty() {
+            return Err("Expected at least one pattern atom.");
+        }
+        Ok(states)
+    }
+
+    fn parse_atom(&mut self) -> Result<State, &'static str> {
+        let (min, max) = self.parse_repeat_count()?;
+        let identifier = self.next_identifier;
+        self.next_identifier += 1;
+
+        let state = match self.peek() {
+            Some('"') => {
+                let literal = State::new_literal(min, max, &self.parse_literal()?);
+                if self.extended && self.eat('i') {
+                    literal.set_case_insensitive(true)
+                } else {
+                    literal
+                }
+            }
+
+            Some('(') => {
+                self.pos += 1;
+                let mut options = vec![State::new_option(self.parse_sequence()?)];
+                while self.eat(',') {
+                    options.push(State::new_option(self.parse_sequence()?));
+                }
+                if !self.eat(')') {
+                    return Err("Unbalanced parentheses in pattern.");
+                }
+
+                // Each repetition gets to pick its own option, so the coalesce sits inside the repeating block.
+                State::new_group(min, max, options)
+            }
+
+            Some('\'') if self.dialect.allows_negated_codes() || self.extended => {
+                self.pos += 1;
+                let mut codes = self.parse_codes()?;
+                let inner = if codes.len() == 1 { codes.pop().unwrap() } else { PatternType::AnyOf(codes) };
+                State::new(min, max, vec![PatternType::Not(Box::new(inner))])
+            }
+
+            Some('\'') => return Err("Negated pattern codes are not supported in this dialect."),
+
+            Some(c) if c.is_alphabetic() || (c == '[' && self.extended) => State::new(min, max, self.parse_codes()?),
+
+            Some(_) => return Err("Expected a pattern code, string literal or group after the repeat count."),
+            None => return Err("Pattern ends with a dangling repeat count."),
+        };
+
+        Ok(state.set_identifier(identifier))
+    }
+
+    /// Reads `n`, `n.m`, `n.`, `.m` or `.`.
+    fn parse_repeat_count(&mut self) -> Result<(usize, Option<usize>), &'static str> {
+        let low = self.parse_number()?;
+        if !self.eat('.') {
+            let exact = low.ok_or("Expected a repeat count.")?;
+            return Ok((exact, Some(exact)));
+        }
+
+        let min = low.unwrap_or(0);
+        let max = self.parse_number()?;
+        if let Some(max_num) = max {
+            if max_num < min {
+                return Err("Repeat count minimum is larger than the maximum.");
+            }
+        }
+        Ok((min, max))
+    }
+
+    fn parse_number(&mut self) -> Result<Option<usize>, &'static str> {
+        let mut number: Option<usize> = None;
+        while let Some(digit) = self.peek().and_then(|c| c.to_digit(10)) {
+            let so_far = number.unwrap_or(0);
+            number = Some(so_far.checked_mul(10).and_then(|n| n.checked_add(digit as usize)).ok_or("Repeat count is too large.")?);
+            self.pos += 1;
+        }
+        Ok(number)
+    }
+
+    fn parse_codes(&mut self) -> Result<Vec<PatternType>, &'static str> {
+        let mut patterns = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '[' && self.extended {
+                patterns.push(self.parse_bracket()?);
+                continue;
+            }
+            if !c.is_alphabetic() {
+                break;
+            }
+            match PatternType::from_code(c) {
+                Some(pattern) => patterns.push(pattern),
+                None if self.dialect.allows_user_codes() => {
+                    if !self.table.as_ref().is_some_and(|t| t.is_defined(c)) {
+                        return Err("Pattern code is not defined by any pattern table.");
+                    }
+                    patterns.push(PatternType::Custom(c.to_ascii_uppercase()));
+                }
+                None => return Err("Unknown pattern code."),
+            }
+            self.pos += 1;
+        }
+
+        if patterns.is_empty() {
+            return Err("Expected a pattern code.");
+        }
+        Ok(patterns)
+    }
+
+    /// Reads an extended syntax set like `[A-F0-9_]` or `[^"]`. A backslash takes the next character as is.
+    fn parse_bracket(&mut self) -> Result<PatternType, &'static str> {
+        self.pos += 1; // Opening bracket
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        loop {
+            let low = match self.peek() {
+                None => return Err("Unterminated character set in pattern."),
+                Some(']') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(_) => self.bracket_char()?,
+            };
+
+            // A dash right before the closing bracket is just a dash.
+            let high = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                self.bracket_char()?
+            } else {
+                low
+            };
+            if high < low {
+                return Err("Character range runs backwards.");
+            }
+            ranges.push((low, high));
+        }
+
+        if ranges.is_empty() {
+            return Err("Empty character set in pattern.");
+        }
+        let set = match ranges[..] {
+            [(low, high)] => PatternType::Range(low, high),
+            _ => PatternType::new_set(ranges),
+        };
+        Ok(if negated { PatternType::Not(Box::new(set)) } else { set })
+    }
+
+    fn bracket_char(&mut self) -> Result<char, &'static str> {
+        self.eat('\\');
+        let c = self.peek().ok_or("Unterminated character set in pattern.")?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    /// Reads a quoted string, where a doubled quote stands for a quote character.
+    fn parse_literal(&mut self) -> Result<String, &'static str> {
+        self.pos += 1; // Opening quote
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                None => return Err("Unterminated string literal in pattern."),
+                Some('"') => {
+                    self.pos += 1;
+                    if !self.eat('"') {
+                        return Ok(text);
+                    }
+                    text.push('"');
+                }
+                Some(c) => {
+                    self.pos += 1;
+                    text.push(c);
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)] // The original tests, kept as written.
 mod tests {
     use super::*;
 
@@ -154,4 +446,68 @@ mod tests {
         let test_result3 = builder3.process();
         assert_eq!(test_result3.is_ok(), true);
     }
+
+    #[test]
+    fn literal_atoms() {
+        let build = |text: &str| Builder::init_default(text.to_string()).build().unwrap();
+
+        let pattern = build("2\"AB\"");
+        assert_eq!(pattern.get_states().len(), 1);
+        assert_eq!(pattern.get_states()[0].get_literal(), Some("AB"));
+        assert!(pattern.is_match("ABAB").unwrap());
+        assert!(!pattern.is_match("AB").unwrap());
+        assert!(!pattern.is_match("ABA").unwrap());
+
+        // Doubled quotes are a quote character, so this is one quote, then "a""b" is a"b.
+        assert_eq!(build("1\"\"\"\"").get_states()[0].get_literal(), Some("\""));
+        assert!(build("1\"\"\"\"1\"a\"\"b\"").is_match("\"a\"b").unwrap());
+        assert!(build("1\"\"1N").is_match("5").unwrap());
+        assert!(build(".E1\"--\".E").is_match("a--b--c").unwrap());
+        assert!(build("0.3\"AB\"1\"A\"").is_match("ABABA").unwrap());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let pattern = Builder::init_default("1\"yes\"1(1\"-\",1\"x\")".to_string()).set_case_insensitive(true).build().unwrap();
+        assert!(pattern.is_match("YES-").unwrap());
+        assert!(pattern.is_match("yEsX").unwrap());
+        assert!(!pattern.is_match("YES+").unwrap());
+        assert!(!Builder::init_default("1\"yes\"".to_string()).build().unwrap().is_match("YES").unwrap());
+
+        // U and L keep their meaning.
+        let upper = Builder::init_default("1U1\"a\"".to_string()).set_case_insensitive(true).build().unwrap();
+        assert!(upper.is_match("BA").unwrap());
+        assert!(!upper.is_match("ba").unwrap());
+
+        // Per literal, with extended syntax.
+        let extended = |text: &str| Builder::init_default(text.to_string()).set_extended_syntax(true).build().unwrap();
+        let pattern = extended("1\"yes\"i1\"NO\"");
+        assert!(pattern.is_match("YeSNO").unwrap());
+        assert!(!pattern.is_match("yesno").unwrap());
+
+        let sets = extended("1[a-c]1[^x]").set_case_insensitive(true);
+        assert!(sets.is_match("By").unwrap());
+        assert!(!sets.is_match("BX").unwrap());
+        assert!(!sets.is_match("Dy").unwrap());
+    }
+
+    #[test]
+    fn extended_syntax() {
+        let text = "2[A-F0-9]1'[0-9]1[^\"]1N[_\\]-]";
+        assert!(Builder::init_default(text.to_string()).build().is_err());
+
+        let pattern = Builder::init_default(text.to_string()).set_extended_syntax(true).build().unwrap();
+        assert!(pattern.is_match("F0x'_").unwrap());
+        assert!(pattern.is_match("9Ax]5").unwrap());
+        assert!(pattern.is_match("00!!-").unwrap());
+        assert!(!pattern.is_match("0G!!-").unwrap());
+        assert!(!pattern.is_match("001!-").unwrap());
+        assert!(!pattern.is_match("00!\"-").unwrap());
+
+        let extended = |text: &str| Builder::init_default(text.to_string()).set_extended_syntax(true).build();
+        assert!(extended("1[]").is_err());
+        assert!(extended("1[z-a]").is_err());
+        assert!(extended("1[abc").is_err());
+        assert!(extended("1'AN").unwrap().is_match("-").unwrap());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,181 @@
+use super::alphabet::Alphabet;
+use super::automaton::Dfa;
+use super::matcher::Matcher;
+use super::pattern::Pattern;
+use super::rule::State;
+
+#[derive(Clone, Debug)]
+enum Expression {
+    Pattern(usize),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+}
+
+impl Expression {
+    /// Points every pattern this refers to `by` further along, for when it's put after another expression's patterns.
+    fn shift(self, by: usize) -> Self {
+        match self {
+            Self::Pattern(index) => Self::Pattern(index + by),
+            Self::And(a, b) => Self::And(Box::new(a.shift(by)), Box::new(b.shift(by))),
+            Self::Or(a, b) => Self::Or(Box::new(a.shift(by)), Box::new(b.shift(by))),
+            Self::Not(a) => Self::Not(Box::new(a.shift(by))),
+        }
+    }
+}
+
+/// Patterns put together with and, or and not, which M syntax has no way to say. Everything gets compiled into one
+/// automaton, which only knows the ASCII classes, so any pattern classifying characters another way makes matching an
+/// Err. Can be combined further.
+#[derive(Clone, Debug)]
+pub struct Combination {
+    patterns: Vec<Pattern>,
+    expression: Expression,
+    automaton: Dfa,
+    ascii: bool,
+}
+
+impl From<Pattern> for Combination {
+    fn from(pattern: Pattern) -> Self {
+        Self::compile(vec![pattern], Expression::Pattern(0))
+    }
+}
+
+impl From<&Pattern> for Combination {
+    fn from(pattern: &Pattern) -> Self {
+        Self::from(pattern.clone())
+    }
+}
+
+impl From<&Combination> for Combination {
+    fn from(combination: &Combination) -> Self {
+        combination.clone()
+    }
+}
+
+impl Combination {
+    fn compile(patterns: Vec<Pattern>, expression: Expression) -> Self {
+        let states: Vec<State> = patterns.iter().flat_map(|p| p.get_states()).cloned().collect();
+        let alphabet = Alphabet::separating(&states);
+        let automaton = Self::build(&patterns, &expression, &alphabet);
+        let ascii = patterns.iter().all(Pattern::is_ascii_classified);
+        Self { patterns, expression, automaton, ascii }
+    }
+
+    fn build(patterns: &[Pattern], expression: &Expression, alphabet: &Alphabet) -> Dfa {
+        match expression {
+            Expression::Pattern(index) => patterns[*index].to_automaton(alphabet.clone()),
+            Expression::And(a, b) => Self::build(patterns, a, alphabet).product(&Self::build(patterns, b, alphabet), |a, b| a && b),
+            Expression::Or(a, b) => Self::build(patterns, a, alphabet).product(&Self::build(patterns, b, alphabet), |a, b| a || b),
+            Expression::Not(a) => Self::build(patterns, a, alphabet).complement(),
+        }
+    }
+
+    /// Both expressions over one list of patterns, the other's moved along past this one's.
+    fn join(&self, other: Combination) -> (Vec<Pattern>, Expression, Expression) {
+        let patterns = self.patterns.iter().cloned().chain(other.patterns).collect();
+        (patterns, self.expression.clone(), other.expression.shift(self.patterns.len()))
+    }
+
+    /// Matches what both of them match.
+    pub fn intersection<C: Into<Combination>>(&self, other: C) -> Combination {
+        let (patterns, a, b) = self.join(other.into());
+        Self::compile(patterns, Expression::And(Box::new(a), Box::new(b)))
+    }
+
+    /// Matches what either of them matches.
+    pub fn union<C: Into<Combination>>(&self, other: C) -> Combination {
+        let (patterns, a, b) = self.join(other.into());
+        Self::compile(patterns, Expression::Or(Box::new(a), Box::new(b)))
+    }
+
+    /// Matches whatever this doesn't.
+    pub fn complement(&self) -> Combination {
+        Self::compile(self.patterns.clone(), Expression::Not(Box::new(self.expression.clone())))
+    }
+
+    /// The patterns that went into it, in the order they were combined.
+    pub fn get_patterns(&self) -> &Vec<Pattern> {
+        &self.patterns
+    }
+
+    pub fn get_automaton(&self) -> &Dfa {
+        &self.automaton
+    }
+
+    /// A shortest string it matches, if there's any at all.
+    pub fn shortest_example(&self) -> Result<Option<String>, &'static str> {
+        self.check_ascii()?;
+        Ok(self.automaton.shortest())
+    }
+
+    fn check_ascii(&self) -> Result<(), &'static str> {
+        if self.ascii {
+            Ok(())
+        } else {
+            Err("Only patterns that classify characters the ASCII way can be combined.")
+        }
+    }
+}
+
+impl Matcher for Combination {
+    fn is_match(&self, input: &str) -> Result<bool, &'static str> {
+        self.check_ascii()?;
+        Ok(self.automaton.accepts(input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::rule::Classification;
+    use crate::regex::testing::compile;
+
+    #[test]
+    fn combines_patterns() {
+        let (digits, three) = (compile(".N"), compile("3E"));
+        let both = digits.intersection(&three);
+        assert!(both.is_match("123").unwrap());
+        assert!(!both.is_match("12a").unwrap());
+        assert!(!both.is_match("1234").unwrap());
+        assert_eq!(both.shortest_example().unwrap().as_deref(), Some("000"));
+
+        let either = digits.union(&three);
+        assert!(either.is_match("12a").unwrap() && either.is_match("1234").unwrap());
+        assert!(!either.is_match("12ab").unwrap());
+
+        let neither = either.complement();
+        assert_eq!(neither.count_matches(["", "1", "ab", "a1b2", "\u{e9}"]).unwrap(), 3);
+
+        // Not (A or B) is the same as not A and not B, whichever way it's put together.
+        let other_way = digits.complement().intersection(three.complement());
+        let site = compile("2U1\"-\"3N").complement();
+        for input in ["", "12a", "AB-123", "1234", "ab-cd", "AB-12", "\u{e9}\u{e9}\u{e9}"] {
+            assert_eq!(neither.is_match(input), other_way.is_match(input), "{:?}", input);
+            assert_eq!(site.is_match(input).unwrap(), !compile("2U1\"-\"3N").is_match(input).unwrap(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn matchers_are_interchangeable() {
+        fn accepts_all<M: Matcher>(matcher: &M, inputs: &[&str]) -> bool {
+            matcher.count_matches(inputs.iter().copied()).unwrap() == inputs.len()
+        }
+        let site = compile("2U1\"-\"3N");
+        let national = compile("1.2A1P.N");
+        let inputs = ["AB-123", "XY-000"];
+        assert!(accepts_all(&site, &inputs));
+        assert!(accepts_all(&site.intersection(&national), &inputs));
+        assert!(!accepts_all(&site.intersection(national.complement()), &inputs));
+    }
+
+    #[test]
+    fn only_ascii_patterns_combine() {
+        // Under Unicode, a letter with no case is still A, which the automaton has no way of knowing.
+        let letters = compile("1A").set_classification(Classification::Unicode);
+        assert!(letters.is_match("\u{4e2d}").unwrap());
+        assert!(letters.union(compile("1N")).is_match("\u{4e2d}").is_err());
+        assert!(compile("1N").intersection(&letters).shortest_example().is_err());
+        assert!(compile("1N").complement().is_match("\u{4e2d}").unwrap());
+    }
+}
@@ -0,0 +1,103 @@
+use super::rule::Classification;
+
+/// The M implementations disagree on what a pattern may contain. The Builder uses this to decide which syntax it
+/// accepts and how the compiled pattern classifies characters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// Plain ANSI/ISO M (X11.1-1995). Only the seven standard codes, ASCII classes.
+    #[default]
+    Ansi1995,
+    /// GT.M and YottaDB in M mode. Letters outside the standard seven are user-defined codes from a pattern table.
+    Gtm,
+    /// InterSystems Caché/IRIS. Codes can be negated with `'`, and letters go by Unicode.
+    InterSystems,
+}
+
+impl Dialect {
+    pub fn classification(&self) -> Classification {
+        match self {
+            Self::Ansi1995 | Self::Gtm => Classification::Ascii,
+            Self::InterSystems => Classification::Unicode,
+        }
+    }
+
+    /// Whether `1'N` (anything that isn't numeric) is valid.
+    pub fn allows_negated_codes(&self) -> bool {
+        matches!(self, Self::InterSystems)
+    }
+
+    /// Whether letters besides A, C, E, L, N, P and U can be pattern codes at all.
+    pub fn allows_user_codes(&self) -> bool {
+        matches!(self, Self::Gtm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::builder::Builder;
+
+    // (pattern, input, expected). None means the dialect rejects the pattern text itself.
+    type ConformanceTable = [(&'static str, &'static str, Option<bool>)];
+
+    const ANSI_1995: &ConformanceTable = &[
+        ("3N1\"-\"4N", "555-1234", Some(true)),
+        ("1U.L", "Smith", Some(true)),
+        ("1U.L", "smith", Some(false)),
+        (".P", " ,.!", Some(true)),
+        ("1C", "\t", Some(true)),
+        ("1A", "\u{e9}", Some(false)),
+        ("1E", "\u{e9}", Some(true)),
+        ("1'N", "A", None),
+        ("1X", "A", None),
+    ];
+
+    const GTM: &ConformanceTable = &[
+        ("3N1\"-\"4N", "555-1234", Some(true)),
+        ("1A", "\u{416}", Some(false)),
+        ("1U", "\u{c9}", Some(false)),
+        ("1'N", "A", None),
+        ("1X", "A", None), // Syntax is fine, but there's no table defining X.
+    ];
+
+    const INTERSYSTEMS: &ConformanceTable = &[
+        ("3N1\"-\"4N", "555-1234", Some(true)),
+        ("1A", "\u{e9}", Some(true)),
+        ("1U", "\u{c9}", Some(true)),
+        ("1A", "\u{416}", Some(true)),
+        ("1'N", "A", Some(true)),
+        ("1'N", "5", Some(false)),
+        ("1'AN", "-", Some(true)),
+        ("1'AN", "x", Some(false)),
+        (".'P", "abc", Some(true)),
+        ("1X", "A", None),
+    ];
+
+    fn check_table(dialect: Dialect, table: &ConformanceTable) {
+        for (text, input, expected) in table {
+            let built = Builder::init_default(text.to_string()).set_dialect(dialect).build();
+            match expected {
+                None => assert!(built.is_err(), "{:?} should reject {}", dialect, text),
+                Some(result) => {
+                    let pattern = built.unwrap_or_else(|e| panic!("{:?} rejected {}: {}", dialect, text, e));
+                    assert_eq!(pattern.is_match(input).unwrap(), *result, "{:?}: {:?} ? {}", dialect, input, text);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ansi_conformance() {
+        check_table(Dialect::Ansi1995, ANSI_1995);
+    }
+
+    #[test]
+    fn gtm_conformance() {
+        check_table(Dialect::Gtm, GTM);
+    }
+
+    #[test]
+    fn intersystems_conformance() {
+        check_table(Dialect::InterSystems, INTERSYSTEMS);
+    }
+}
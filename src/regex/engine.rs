@@ -1,10 +1,59 @@
+use std::cmp::Ordering;
+
+use crate::NO_STATES_ERROR;
+
 use super::rule::State;
-use super::helpers::Cache;
+use super::helpers::{sequence_length_bounds, Cache, UndoLog};
+
+/// Which parse wins when an input can be split up between the states more than one way. Whole-string matching gives
+/// the same yes or no either way, but the substrings each state ends up with differ.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Disambiguation {
+    /// Fewest characters and repetitions first, so later states get as much as they can.
+    #[default]
+    Lazy,
+    /// Most characters and repetitions first. Coalesce options still go in order, and the first one that works wins.
+    Greedy,
+    /// POSIX style. Going left to right, every state takes the longest span it can while the rest still matches, a block
+    /// as a whole before what's inside it, and that goes for coalesce options too. Goes through every parse that could
+    /// still come out on top, so it's the slowest of the three, but only when the parse is asked for. Checking whether
+    /// there's a match at all stops at the first one.
+    LeftmostLongest,
+}
+
+/// Knobs for how the Engine, and the Programs patterns compile to, go about matching.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EngineConfig {
+    disambiguation: Disambiguation,
+}
+
+impl EngineConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_disambiguation(mut self, disambiguation: Disambiguation) -> Self {
+        self.disambiguation = disambiguation;
+        self
+    }
+
+    pub fn get_disambiguation(&self) -> Disambiguation {
+        self.disambiguation
+    }
+}
+
+/// Compares two parses of the same input, given as where each state and block in them ended, in the order they start
+/// in the pattern with a block before what's inside it. The one whose first differing state runs further is the
+/// leftmost-longest one.
+pub fn compare_leftmost_longest(a: &[usize], b: &[usize]) -> Ordering {
+    a.iter().zip(b).map(|(x, y)| x.cmp(y)).find(|o| o.is_ne()).unwrap_or(Ordering::Equal)
+}
 
 
 pub struct Engine {
     states: Vec<State>,
     input_string: String,
+    input_chars: Vec<char>, // The input again, so positions are a lookup instead of a walk through the string.
 
     // Dynamic vector in which we keep track of progress in case we backtrack. Each entry corresponds to one in the states field.
     // Counts how many characters were recognized per state.
@@ -13,6 +62,21 @@ pub struct Engine {
     // pointer to where we are at, points to the character at index for input_string.
     cur_char_pos: usize,
 
+    // Changes to the states since the run started, so backtracking can put them back without copying anything.
+    undo: UndoLog,
+
+    // Back up by copying everything instead, the way the engine used to. Only useful for comparing the two.
+    #[cfg(any(test, feature = "bench"))]
+    snapshots: bool,
+
+    config: EngineConfig,
+
+    // Leftmost-longest goes through every parse, and keeps the best counts and states it has seen here, along with
+    // what they got compared by.
+    best: Option<(Vec<usize>, Vec<State>, Vec<usize>)>,
+
+    // Whether this run goes through every parse. Only leftmost-longest does, and only when the parse is wanted.
+    exhaustive: bool,
 }
 
 impl Engine {
@@ -21,21 +85,46 @@ impl Engine {
         let state_count = states.len();
         Self {
             states,
+            input_chars: input_string.chars().collect(),
             input_string,
             counts: Vec::with_capacity(state_count),
             cur_char_pos: 0,
+            undo: UndoLog::default(),
+            #[cfg(any(test, feature = "bench"))]
+            snapshots: false,
+            config: EngineConfig::default(),
+            best: None,
+            exhaustive: false,
         }
     }
 
+    pub fn set_config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn get_config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Backtracks by copying the counts and states at every choice point, like the engine used to. Slow, and only kept
+    /// so the benchmarks have something to compare against, which is why it needs the `bench` feature.
+    #[cfg(any(test, feature = "bench"))]
+    pub fn set_snapshot_backtracking(mut self, snapshots: bool) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
     // $$--------- HELPER FUNCTION SECTION START ----------$$
     #[inline]
     pub fn get_input_string(&self) -> &String {
         &self.input_string
     }
 
+    /// In characters, like the cursor.
     #[inline]
     pub fn input_string_len(&self) -> usize {
-        self.get_input_string().len()
+        self.input_chars.len()
     }
 
     #[inline]
@@ -60,7 +149,7 @@ impl Engine {
 
     #[inline]
     pub fn advance(&mut self) -> bool {
-        if self.get_cur_pos() >= (self.get_input_string().len() - 1) {
+        if self.get_cur_pos() + 1 >= self.input_string_len() {
             false // Can't advance
         } else {
             *self.get_cur_pos_mut() += 1;
@@ -70,17 +159,17 @@ impl Engine {
 
     #[inline]
     pub fn finish(&mut self) {
-        *self.get_cur_pos_mut() = self.get_input_string().len()
+        *self.get_cur_pos_mut() = self.input_string_len()
     }
 
     #[inline]
     pub fn is_finished(&self) -> bool {
-        self.get_cur_pos() >= self.get_input_string().len()
+        self.get_cur_pos() >= self.input_string_len()
     }
 
     #[inline]
     pub fn cursor_is_at_end(&self) -> bool {
-        self.get_cur_pos() == (self.get_input_string().len() - 1)
+        self.get_cur_pos() + 1 == self.input_string_len()
     }
 
     #[inline]
@@ -95,7 +184,7 @@ impl Engine {
 
     #[inline]
     pub fn get_cur_char(&self) -> Option<char> {
-        self.get_input_string().chars().nth(self.get_cur_pos())
+        self.input_chars.get(self.get_cur_pos()).copied()
     }
 
     #[inline]
@@ -133,61 +222,84 @@ impl Engine {
     }
 
     fn cache_generate(&self) -> Cache {
-        Cache::new(self.get_counts().clone(), self.get_cur_pos(), self.get_states().clone())
+        #[cfg(any(test, feature = "bench"))]
+        if self.snapshots {
+            return Cache::Snapshot {
+                counts: self.get_counts().clone(),
+                cur_pos: self.get_cur_pos(),
+                states: self.get_states().clone(),
+                log_len: self.undo.len(),
+            };
+        }
+
+        Cache::Checkpoint {
+            counts_len: self.get_counts().len(),
+            last_count: self.get_counts().last().copied().unwrap_or(0),
+            cur_pos: self.get_cur_pos(),
+            log_len: self.undo.len(),
+        }
     }
 
     fn cache_consume(&mut self, cache: Cache) {
-        (*self.get_counts_mut(), *self.get_cur_pos_mut(), *self.get_states_mut()) = cache.extract();
+        match cache {
+            #[cfg(any(test, feature = "bench"))]
+            Cache::Snapshot { counts, cur_pos, states, log_len } => {
+                (self.counts, self.cur_char_pos, self.states) = (counts, cur_pos, states);
+                self.undo.forget(log_len);
+            }
+            checkpoint => self.cache_consume_non_dropping(&checkpoint),
+        }
     }
 
     fn cache_consume_non_dropping(&mut self, cache: &Cache) {
-        self.cache_consume(cache.clone());
+        match cache {
+            #[cfg(any(test, feature = "bench"))]
+            Cache::Snapshot { .. } => self.cache_consume(cache.clone()),
+            Cache::Checkpoint { counts_len, last_count, cur_pos, log_len } => {
+                // Deeper states only ever touch counts from the checkpoint's last one onwards.
+                self.undo.rewind(&mut self.states, *log_len);
+                self.counts.resize(*counts_len, 0);
+                if let Some(last) = self.counts.last_mut() {
+                    *last = *last_count;
+                }
+                self.cur_char_pos = *cur_pos;
+            }
+        }
+    }
+
+    /// Puts the states in place of the one at index, remembering what was there if we'll need to undo it.
+    fn splice_states(&mut self, index: usize, states: Vec<State>) {
+        let inserted = states.len();
+        let removed = self.get_states_mut().splice(index..=index, states).next().unwrap();
+        self.undo.record(index, inserted, removed);
     }
 
     /// Called when handling a block
     fn expand_block_state(&mut self, multiplicity: usize) -> Result<(), &'static str> {
-        if let Some(state) = self.get_current_state() {
-            // Some sanity checks
-            if !state.is_block_type() {
-                return Err("The state is not of a block type.");
-            }
-
-            if !state.within_count(multiplicity) {
-                return Err("Multiplicity is not valid.");
-            }
-
-            // Else, let's go ahead and do this
-            let current_index = self.get_counts().len() - 1;
-            let mut new_states = Vec::from(&self.get_states()[0..current_index]);
-
-            
-            let mut remaining_states = if current_index >= (self.get_states().len() - 1) {
-                Vec::new()
-            } else {
-                Vec::from(&self.get_states()[(current_index + 1)..self.get_states().len()])
-            };
-            let repr_states = state.expand_block_states().unwrap();
-
-            for _i in 0..multiplicity {
-                new_states.append(&mut repr_states.clone())
-            }
-
-            // Now cap the end again.
-            new_states.append(&mut remaining_states);
-
-            *self.get_states_mut() = new_states;
-
+        let state = self.get_current_state().ok_or("Passed in a null state to expand_block_state.")?;
+        // Some sanity checks
+        if !state.is_block_type() {
+            return Err("The state is not of a block type.");
+        }
 
-            Ok(())
+        if !state.within_count(multiplicity) {
+            return Err("Multiplicity is not valid.");
+        }
 
-        } else {
-            Err("Passed in a null state to expand_block_state.")
+        let repr_states = state.get_block_states().unwrap();
+        let mut copies = Vec::with_capacity(repr_states.len() * multiplicity);
+        for _i in 0..multiplicity {
+            copies.extend(repr_states.iter().cloned());
         }
+
+        let current_index = self.get_counts().len() - 1;
+        self.splice_states(current_index, copies);
+        Ok(())
     }
 
     fn replace_state(&mut self, state: State) {
-        let current_index = self.get_states().len() - 1;
-        self.get_states_mut()[current_index] = state;
+        let current_index = self.get_counts().len() - 1;
+        self.splice_states(current_index, vec![state]);
     }
 
     //          $$---------- HELPER FUNCTION SECTION END ----------$$
@@ -196,12 +308,14 @@ impl Engine {
 
     /// Initializes the struct for a processing run.
     pub fn init(&mut self) -> Result<(), &'static str> {
-        let _ = self.get_input_string().chars().nth(0).ok_or("First character not found. Exiting.")?; // Handle a null string, can't regex on it.
-        let _ = self.get_states().iter().nth(0).ok_or("No valid states are found to fulfill. Exiting.")?;
+        // A null string is fine, it just has to be something every state can skip.
+        let _ = self.get_states().first().ok_or(NO_STATES_ERROR)?;
 
         // Make sure we are set up for success, get our first token
         self.get_counts_mut().clear();
         self.get_counts_mut().push(0);
+        self.undo.clear();
+        self.best = None;
         *self.get_cur_pos_mut() = 0;
 
         Ok(())
@@ -209,9 +323,77 @@ impl Engine {
 
     /// Main algorithmic driver for the Engine instance.
     pub fn process(&mut self) -> Result<bool, &'static str> {
+        self.search(self.config.disambiguation == Disambiguation::LeftmostLongest)
+    }
+
+    /// Whether the states match at all. Stops at the first parse it finds whatever the disambiguation, so if it's
+    /// leftmost-longest the results afterwards aren't necessarily the ones process would give.
+    pub fn is_match(&mut self) -> Result<bool, &'static str> {
+        self.search(false)
+    }
+
+    fn search(&mut self, exhaustive: bool) -> Result<bool, &'static str> {
         self.init()?; // If fail initialization, don't bother continuing.
-        self.execute()
-        
+        self.exhaustive = exhaustive;
+
+        // No point backtracking through anything if the input is too short or too long to ever fit.
+        let length = self.input_chars.len();
+        let (low, high) = sequence_length_bounds(self.get_states());
+        if length < low || high.is_some_and(|high| length > high) {
+            return Ok(false);
+        }
+        if self.execute()? {
+            return Ok(true);
+        }
+
+        // Leftmost-longest never stops early, whatever it found along the way is the answer.
+        match self.best.take() {
+            Some((counts, states, _)) => {
+                (self.counts, self.states) = (counts, states);
+                *self.get_cur_pos_mut() = self.input_chars.len();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Called when every state has had its turn. Returns whether to stop looking, which an exhaustive run never does,
+    /// it just remembers the parse if it beats what it had.
+    fn complete(&mut self) -> bool {
+        if !self.is_finished() {
+            return false;
+        }
+        if !self.exhaustive {
+            return true;
+        }
+
+        let outline = self.outline();
+        let better = match &self.best {
+            None => true,
+            Some((_, _, best)) => compare_leftmost_longest(&outline, best).is_gt(),
+        };
+        if better {
+            self.best = Some((self.counts.clone(), self.states.clone(), outline));
+        }
+        false
+    }
+
+    /// Where every state ended, blocks included, for compare_leftmost_longest. The blocks are gone from the states by
+    /// now, so they get put back together by going back over the undo log, each entry turning the states it put in
+    /// back into the block they came from.
+    fn outline(&self) -> Vec<usize> {
+        let mut pieces: Vec<(usize, usize, Vec<usize>)> = spans(&self.counts).into_iter().map(|(start, end)| (start, end, vec![end])).collect();
+        for (index, inserted, _) in self.undo.entries().iter().rev() {
+            let inner: Vec<(usize, usize, Vec<usize>)> = pieces.drain(*index..index + inserted).collect();
+            // A block repeated zero times is empty, right where whatever comes after it starts.
+            let at = pieces.get(*index).map_or(self.get_cur_pos(), |piece| piece.0);
+            let start = inner.first().map_or(at, |piece| piece.0);
+            let end = inner.last().map_or(at, |piece| piece.1);
+            let mut ends = vec![end];
+            ends.extend(inner.into_iter().flat_map(|piece| piece.2));
+            pieces.insert(*index, (start, end, ends));
+        }
+        pieces.into_iter().flat_map(|piece| piece.2).collect()
     }
 
     pub fn extract_results(&self) -> Option<Vec<(State, String)>> {
@@ -219,186 +401,204 @@ impl Engine {
             return None;
         }
 
+        // Counts are in characters, not bytes.
         let mut output = Vec::new();
-        let mut pos: usize = 0;
+        let mut characters = self.get_input_string().chars();
         for entry in self.get_states().iter().zip(self.get_counts()) {
-            let new = pos + entry.1;
-            output.push((entry.0.clone(), self.get_input_string()[pos..new].to_string()));
-            pos = new;
+            output.push((entry.0.clone(), characters.by_ref().take(*entry.1).collect()));
         }
 
         Some(output)
     }
 
     fn execute(&mut self) -> Result<bool, &'static str> {
-        let mut can_qualify_next_state: bool;
-        let mut can_qualify_now: bool;
-        let mut can_skip_next: bool;
-        let mut is_last_state: bool;
-        while !self.is_finished() {
-            // Check if it qualifies for now
-            can_qualify_now = self.evaluate_char_with_limits()?;
-            can_qualify_next_state = self.check_if_qualify_for_next_state();
-            can_skip_next = match self.get_next_state() {
-                None => {
-                    false
+        // Every state still in the middle of trying its alternatives has a frame here, instead of a call of its own, so
+        // a block expanded into thousands of states doesn't need thousands of nested calls.
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut entering = true;
+        loop {
+            if entering {
+                match self.enter()? {
+                    Entered::Frame(frame) => frames.push(frame),
+                    Entered::Done(true) => return Ok(true),
+                    Entered::Done(false) => {}
                 }
+            }
 
-                Some(st) => {
-                    st.allows_skip()
-                }
+            let Some(frame) = frames.last_mut() else {
+                return Ok(false);
             };
-            is_last_state = self.get_next_state().is_none();
-
-            // Block cases are unique, and should be handled foremost. If we hit here, its most likely we handle true/false here.
-            if can_qualify_now && self.get_current_state().unwrap().is_block_type() {
-                let cache = self.cache_generate();
-                // If the block is a coalesce, then it's easier
-                if self.get_current_state().unwrap().is_coalesce() {
-                    let lim = self.get_current_state().unwrap().block_size().unwrap();
-                    for i in 0..lim {
-                        self.cache_consume_non_dropping(&cache); // reset
-                        let c_option = self.get_current_state().unwrap().nth_coalesce_option(i)?;
-                        self.replace_state(c_option);
-
-                        let result = self.execute()?;
-                        if result {
-                            return Ok(true);
-                        }
-                    }
-                    return Ok(false);
+            match self.next_alternative(frame)? {
+                Next::Enter => entering = true,
+                Next::Matched => return Ok(true),
+                Next::Exhausted => {
+                    let frame = frames.pop().unwrap();
+                    self.leave(frame);
+                    entering = false;
                 }
-
-                let block_state = self.get_current_state().unwrap();
-                let state_upper_lim = block_state.get_max().unwrap_or(crate::BLOCK_TRUE_UPPER_LIM);
-
-                for i in block_state.get_min()..=state_upper_lim {
-                    self.cache_consume_non_dropping(&cache); // reset
-                    self.expand_block_state(i)?;
-
-                    let result = self.execute()?;
-                    if result {
-                        return Ok(true);
-                    }
-                }
-
-                // If none of the stuff work above, we simply can't pass
-                return Ok(false);
-
             }
+        }
+    }
 
-            // Easy case, if you can't qualify now but can qualify on the next state, do that. We account for possibility that 
-            // the next state doesn't exist in can_qualify_next_state
-            if !can_qualify_now && can_qualify_next_state {
-                self.advance_state(); // Just let the next iteration of the loop handle the increment
-                // self.add_current_count()?; // This was causing issues all along.
+    /// Starts on the current state, working out everything it could try. Whatever can be settled straight away is.
+    fn enter(&mut self) -> Result<Entered, &'static str> {
+        let index = self.get_counts().len() - 1;
+        if index >= self.get_states().len() {
+            // A block expanded zero times at the very end leaves a dangling count behind.
+            self.get_counts_mut().pop();
+            return Ok(Entered::Done(self.complete()));
+        }
+
+        // Block cases are unique, and should be handled foremost. Every option or multiplicity is a choice point.
+        if self.get_current_state().unwrap().is_block_type() {
+            let cache = self.cache_generate();
+
+            // If the block is a coalesce, then it's easier
+            if self.get_current_state().unwrap().is_coalesce() {
+                // Options that can't start with the next character aren't worth a reset, unless the whole coalesce is
+                // allowed to come up empty.
+                let coalesce = self.get_current_state().unwrap();
+                let next_char = self.get_cur_char();
+                let viable: Vec<usize> = (0..coalesce.block_size().unwrap())
+                    .filter(|i| coalesce.get_min() == 0 || coalesce.get_block_states().unwrap()[*i].first_chars().admits(next_char))
+                    .collect();
+                return Ok(Entered::Frame(Frame::Coalesce { cache, options: viable.into_iter() }));
             }
 
-            if !can_qualify_now && !can_qualify_next_state {
-                // Break down into two cases, one where you can skip the next state and one you can't.
-                // If you can't:
-                if !can_skip_next {
-                    return Ok(false); // out of options.
-                } else {
-                    // If you can, try that
-                    self.advance_state();
-                    if !self.advance_state() {
-                        // Handles case where we try to push past the last state.
-                        return Ok(false);
-                    }
+            let block_state = self.get_current_state().unwrap();
+            let min = block_state.get_min();
 
-                }
+            // If the block can't even take the current character, every copy has to come up empty, so more copies than
+            // the minimum won't get us anywhere.
+            let takes_char = self.get_cur_char().is_some_and(|c| block_state.does_char_qualify(c));
+            if !takes_char && min > 0 && !block_state.first_chars().repetition_can_be_empty() {
+                return Ok(Entered::Done(false)); // Not even the required copies can come up empty.
             }
 
-
-            // Easiest case would be if the character can qualify now, but not later.
-            if can_qualify_now && !can_qualify_next_state {
-                self.add_current_count()?;
-                if is_last_state && self.cursor_is_at_end() {
-                    self.finish();
-                } else {
-                    self.advance();
-                }
-                continue;
+            // Every copy takes at least `per_copy` characters, so only so many fit in what's left. When copies can be
+            // empty, past the minimum only the ones that eat at least a character are worth having, since an empty
+            // copy can always be dropped.
+            let remaining = self.input_chars.len() - self.get_cur_pos();
+            let per_copy = sequence_length_bounds(block_state.get_block_states().unwrap()).0;
+            let fits = if takes_char { remaining.checked_div(per_copy).unwrap_or(remaining.max(min)) } else { min };
+            let state_upper_lim = block_state.get_max().map_or(fits, |max| max.min(fits));
+            if state_upper_lim < min {
+                self.cache_consume(cache);
+                return Ok(Entered::Done(false));
             }
 
-            // More complex case
-            if can_qualify_now && can_qualify_next_state {
-                // We can employ some backtracking to cover our bases
-                let cache = self.cache_generate();
-
-                // Let's try to move it to the next state and try
-                self.advance_state();
-                let result = self.execute()?;
-                if result {
-                    return Ok(true);
-                } else {
-                    // Otherwise, we'll just take a safe route and consume it now.
-                    self.cache_consume(cache);
-                    self.add_current_count()?;
-                    self.advance();
-                }
+            let mut multiplicities: Vec<usize> = (min..=state_upper_lim).collect();
+            if self.config.disambiguation == Disambiguation::Greedy {
+                multiplicities.reverse();
             }
+            return Ok(Entered::Frame(Frame::Block { cache, multiplicities: multiplicities.into_iter() }));
         }
 
-        Ok(self.validate())
-    }
-
-    fn validate(&self) -> bool {
-        if self.get_counts().len() != self.get_states().len() {
-            return false;
+        // Regular state, see how far it could possibly run and then try every length, shortest first.
+        let state = self.get_current_state().unwrap();
+        let start = self.get_cur_pos();
+        let (step, run) = state.longest_run(self.input_chars[start..].iter().copied());
+        let longest = match state.get_max() {
+            None => run,
+            Some(max) => run.min(max),
+        };
+
+        let mut lengths: Vec<usize> = (state.get_min()..=longest).collect();
+        if self.config.disambiguation != Disambiguation::Lazy {
+            lengths.reverse();
         }
+        Ok(Entered::Frame(Frame::Plain { index, start, step, lengths: lengths.into_iter() }))
+    }
+
+    /// Sets up the next thing the state in the frame can try, if there's anything left.
+    fn next_alternative(&mut self, frame: &mut Frame) -> Result<Next, &'static str> {
+        match frame {
+            Frame::Coalesce { cache, options } => {
+                let Some(i) = options.next() else {
+                    return Ok(Next::Exhausted);
+                };
+                self.cache_consume_non_dropping(cache); // reset
+                let c_option = self.get_current_state().unwrap().nth_coalesce_option(i)?;
+                self.replace_state(c_option);
+                Ok(Next::Enter)
+            }
+            Frame::Block { cache, multiplicities } => {
+                let Some(i) = multiplicities.next() else {
+                    return Ok(Next::Exhausted);
+                };
+                self.cache_consume_non_dropping(cache); // reset
+                self.expand_block_state(i)?;
+                Ok(Next::Enter)
+            }
+            Frame::Plain { index, start, step, lengths } => {
+                for units in lengths.by_ref() {
+                    // Throw away whatever the states after this one left behind last time.
+                    self.get_counts_mut().truncate(*index + 1);
+                    let taken = units * *step;
+                    self.get_counts_mut()[*index] = taken;
+                    *self.get_cur_pos_mut() = *start + taken;
 
-        for i in 0..self.get_counts().len() {
-            if !self.get_states()[i].within_count(self.get_counts()[i]) {
-                return false;
+                    if !self.advance_state() {
+                        // Last state, so whatever is left of the input can't be matched anymore.
+                        if self.complete() {
+                            return Ok(Next::Matched);
+                        }
+                        continue;
+                    }
+                    return Ok(Next::Enter);
+                }
+                Ok(Next::Exhausted)
             }
         }
-
-        true
     }
 
-    fn check_if_qualify_for_next_state(&self) -> bool {
-        let next = self.get_states().get(self.get_counts().len());
-        if let None = next {
-            return false; // We have no more
-        } else {
-            let next_state = next.unwrap();
-            let ch = self.get_cur_char();
-            if let None = ch {
-                return false;
+    /// Leave things the way we found them for whoever backtracks into us.
+    fn leave(&mut self, frame: Frame) {
+        match frame {
+            Frame::Coalesce { cache, .. } | Frame::Block { cache, .. } => self.cache_consume(cache),
+            Frame::Plain { index, start, .. } => {
+                self.get_counts_mut().truncate(index + 1);
+                self.get_counts_mut()[index] = 0;
+                *self.get_cur_pos_mut() = start;
             }
-            
-            // Otherwise, we see if this is valid.
-            let the_ch = ch.unwrap();
-            let decision = next_state.does_char_qualify(the_ch) && next_state.within_count(1);
-            return decision;
         }
     }
+}
 
-    /// Checks if the current pointer character can qualify for the bound in the state.
-    fn evaluate_char_with_limits(&self) -> Result<bool, &'static str> {
-        let cur_char = self.get_cur_char().ok_or("Failed to get current character or out of bounds")?;
+/// A state partway through its alternatives, waiting to hear whether the states after it work out.
+enum Frame {
+    Coalesce { cache: Cache, options: std::vec::IntoIter<usize> },
+    Block { cache: Cache, multiplicities: std::vec::IntoIter<usize> },
+    Plain { index: usize, start: usize, step: usize, lengths: std::vec::IntoIter<usize> },
+}
 
-        // We also assume that the last element of the counts field corresponds to the current state we're evaluating this character for.
-        let state = self.get_current_state().ok_or("Failed to retrieve state based on curent counts information.")?;
+/// How starting on a state went: either it has alternatives to go through, or it's already decided.
+enum Entered {
+    Frame(Frame),
+    Done(bool),
+}
 
-        if !state.does_char_qualify(cur_char) {
-            // Basically just not what the state expects, quit early here.
-            Ok(false)
-        } else {
-            // Do some more checking. We make sure to increment by one to see if this character can be added to the count
-            let last_count = *self.get_counts().last().ok_or("No count was found.")?;
-            if state.within_upper_count(last_count + 1) {
-                Ok(true)
-            } else {
-                Ok(false)
-            }
-        }
-    }
+/// What a frame did with its next alternative.
+enum Next {
+    /// Set it up, so it's on to the next state.
+    Enter,
+    /// It finished off the input, and that's the parse.
+    Matched,
+    /// There's nothing left to try.
+    Exhausted,
+}
+
+/// Turns per-state character counts into the span each state took.
+fn spans(counts: &[usize]) -> Vec<(usize, usize)> {
+    let mut start = 0;
+    counts.iter().map(|count| {
+        start += count;
+        (start - count, start)
+    }).collect()
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::vec_init_then_push)] // The original tests, kept as written.
 mod tests {
     use super::*;
     use crate::regex::rule::PatternType;
@@ -440,4 +640,100 @@ mod tests {
 
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn cursor_counts_characters() {
+        let mut engine = Engine::new("\u{e9}t\u{e9}".to_string(), vec![State::new(1, None, vec![PatternType::Alphabetic])]);
+        assert_eq!(engine.input_string_len(), 3);
+        assert!(engine.advance() && engine.advance());
+        assert!(engine.cursor_is_at_end() && !engine.advance());
+        engine.finish();
+        assert!(engine.is_finished());
+    }
+
+    #[test]
+    fn literal_states() {
+        let states = vec![
+            State::new_literal(1, Some(2), "AB"),
+            State::new_literal(1, None, "AB"),
+            State::new(0, None, vec![PatternType::Numeric]),
+        ];
+        let mut engine = Engine::new("ABABAB12".to_string(), states.clone());
+        assert!(engine.process().unwrap());
+        let results = engine.extract_results().unwrap();
+        assert_eq!(results[0].1, "AB"); // Lazy, the first literal gives up everything it can.
+        assert_eq!(results[1].1, "ABAB");
+        assert_eq!(results[2].1, "12");
+
+        let mut engine = Engine::new("ABA".to_string(), states);
+        assert!(!engine.process().unwrap());
+    }
+
+    #[test]
+    fn undo_log_matches_snapshots() {
+        use crate::regex::builder::Builder;
+
+        let texts = ["3N1\"-\"2N1\"-\"4N", "1.3(1A,1N1\".\")", ".(2N,1\"x\"1A).E", "2(1\"ab\",1N)1.2(.2A1N)"];
+        let inputs = ["", "123-45-6789", "a1.b", "x12xA...", "ab12a1", "AB", "1.1.1.", "ab7a1b2"];
+        for text in texts {
+            let states = Builder::init_default(text.to_string()).build().unwrap().get_states().clone();
+            for input in inputs {
+                let mut undo = Engine::new(input.to_string(), states.clone());
+                let mut snapshot = Engine::new(input.to_string(), states.clone()).set_snapshot_backtracking(true);
+                assert_eq!(undo.process().unwrap(), snapshot.process().unwrap(), "{} on {:?}", text, input);
+                let segments = |engine: &Engine| engine.extract_results().map(|r| r.into_iter().map(|(_, s)| s).collect::<Vec<String>>());
+                assert_eq!(segments(&undo), segments(&snapshot), "{} on {:?}", text, input);
+            }
+        }
+    }
+
+    #[test]
+    fn unbounded_blocks_have_no_cap() {
+        // More copies than the old cap of 1000, each one its own state once the block is expanded.
+        let digits = State::new_block(1, None, vec![State::new(1, Some(1), vec![PatternType::Numeric])]);
+        assert!(Engine::new("7".repeat(1500), vec![digits]).process().unwrap());
+
+        // Two characters a copy means an odd length can't work out, whatever the count.
+        let pairs = State::new_block(1, None, vec![State::new(2, Some(2), vec![PatternType::Numeric])]);
+        assert!(Engine::new("123456".to_string(), vec![pairs.clone()]).process().unwrap());
+        assert!(!Engine::new("1234567".to_string(), vec![pairs]).process().unwrap());
+    }
+
+    #[test]
+    fn disambiguation_modes() {
+        let split = |states: &Vec<State>, input: &str, disambiguation| {
+            let config = EngineConfig::new().set_disambiguation(disambiguation);
+            let mut engine = Engine::new(input.to_string(), states.clone()).set_config(config);
+            assert!(engine.process().unwrap());
+            engine.extract_results().unwrap().into_iter().map(|(_, s)| s).collect::<Vec<String>>()
+        };
+
+        let digits = vec![State::new(0, None, vec![PatternType::Numeric]), State::new(0, None, vec![PatternType::Numeric])];
+        assert_eq!(split(&digits, "1234", Disambiguation::Lazy), vec!["", "1234"]);
+        assert_eq!(split(&digits, "1234", Disambiguation::Greedy), vec!["1234", ""]);
+        assert_eq!(split(&digits, "1234", Disambiguation::LeftmostLongest), vec!["1234", ""]);
+
+        // Greedy takes the first option that works, leftmost-longest the one that runs furthest.
+        let options = vec![
+            State::new_coalesce_block(1, Some(1), vec![State::new_literal(1, Some(1), "a"), State::new_literal(1, Some(1), "ab")]),
+            State::new(0, None, vec![PatternType::Everything]),
+        ];
+        assert_eq!(split(&options, "abc", Disambiguation::Lazy), vec!["a", "bc"]);
+        assert_eq!(split(&options, "abc", Disambiguation::Greedy), vec!["a", "bc"]);
+        assert_eq!(split(&options, "abc", Disambiguation::LeftmostLongest), vec!["ab", "c"]);
+
+        // A repeated block is one state as far as leftmost-longest goes, so it gets everything before the state after
+        // it does, however many repetitions that takes.
+        let digit = State::new(1, Some(1), vec![PatternType::Numeric]);
+        let grouped = vec![State::new_block(0, None, vec![digit.clone()]), State::new(0, None, vec![PatternType::Numeric])];
+        assert_eq!(split(&grouped, "12", Disambiguation::Lazy), vec!["12"]);
+        assert_eq!(split(&grouped, "12", Disambiguation::LeftmostLongest), vec!["1", "2", ""]);
+        let grouped = vec![State::new_block(1, Some(3), vec![digit]), State::new(0, None, vec![PatternType::Numeric])];
+        assert_eq!(split(&grouped, "123", Disambiguation::LeftmostLongest), vec!["1", "2", "3", ""]);
+
+        // Any way of cutting the input up works, far too many to go through, but whether it matches is the first one.
+        let pieces = vec![State::new_block(0, None, vec![State::new(1, None, vec![PatternType::Everything])])];
+        let config = EngineConfig::new().set_disambiguation(Disambiguation::LeftmostLongest);
+        assert!(Engine::new("x".repeat(40), pieces).set_config(config).is_match().unwrap());
+    }
+}
@@ -0,0 +1,93 @@
+use super::rule::{PatternType, State};
+
+/// The shortest string the states match, going through them in order and taking as few repetitions as allowed. None if
+/// they can't match anything at all.
+pub fn shortest_example(states: &[State]) -> Option<String> {
+    states.iter().map(|state| example(state, false)).collect()
+}
+
+/// The longest string the states match, or None if there's no limit on how long a match can get.
+pub fn longest_example(states: &[State]) -> Option<String> {
+    states.iter().map(|state| example(state, true)).collect()
+}
+
+fn example(state: &State, longest: bool) -> Option<String> {
+    let times = if longest { state.get_max()? } else { state.get_min() };
+    if times == 0 {
+        return Some(String::new());
+    }
+
+    match state.get_block_states() {
+        // A coalesce repeats the one option, so it's whichever comes out shortest (or longest) once repeated.
+        Some(options) if state.is_coalesce() => {
+            // Options that can't match anything don't count, but any other one without a limit takes the limit away.
+            let viable: Vec<&State> = options.iter().filter(|option| example(option, false).is_some()).collect();
+            if longest && viable.iter().any(|option| option.length_bounds().1.is_none()) {
+                return None;
+            }
+            let examples = viable.into_iter().filter_map(|option| example(option, longest));
+            let chosen = if longest { examples.rev().max_by_key(|e| e.chars().count()) } else { examples.min_by_key(|e| e.chars().count()) };
+            Some(chosen?.repeat(times))
+        }
+        Some(states) => Some(states.iter().map(|s| example(s, longest)).collect::<Option<String>>()?.repeat(times)),
+        None => match state.get_literal() {
+            Some(text) => Some(text.repeat(times)),
+            None => Some(readable_char(state)?.to_string().repeat(times)),
+        },
+    }
+}
+
+/// A character a person would expect to see for the state, like 'A' for letters and '0' for digits, falling back to the
+/// first one that works.
+pub fn readable_char(state: &State) -> Option<char> {
+    let preferred = state.get_patterns().iter().filter_map(preferred_char);
+    let fallback = "Aa0-X".chars().chain(' '..='~').chain('\u{0}'..'\u{250}');
+    preferred.chain(fallback).find(|c| state.does_char_qualify(*c))
+}
+
+fn preferred_char(pattern: &PatternType) -> Option<char> {
+    match pattern {
+        PatternType::Alphabetic | PatternType::Uppercase => Some('A'),
+        PatternType::Lowercase => Some('a'),
+        PatternType::Numeric => Some('0'),
+        PatternType::Punctuation => Some('-'),
+        PatternType::Control => Some('\t'),
+        PatternType::Everything => Some('X'),
+        PatternType::Specific(c) | PatternType::Range(c, _) => Some(*c),
+        PatternType::Set(ranges) => ranges.first().map(|range| range.0),
+        PatternType::Literal(text) => text.chars().next(),
+        PatternType::AnyOf(patterns) => patterns.iter().find_map(preferred_char),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::builder::Builder;
+
+    fn states(text: &str) -> Vec<State> {
+        Builder::init_default(text.to_string()).set_extended_syntax(true).build().unwrap().get_states().clone()
+    }
+
+    #[test]
+    fn shortest_and_longest() {
+        assert_eq!(shortest_example(&states("3N1\"-\"2N1\"-\"4N")).as_deref(), Some("000-00-0000"));
+        assert_eq!(longest_example(&states("3N1\"-\"2N1\"-\"4N")).as_deref(), Some("000-00-0000"));
+        assert_eq!(shortest_example(&states("1.3(1A,1N1\".\")")).as_deref(), Some("A"));
+        assert_eq!(longest_example(&states("1.3(1A,1N1\".\")")).as_deref(), Some("0.0.0."));
+        assert_eq!(shortest_example(&states("2U.L1E")).as_deref(), Some("AAX"));
+        assert_eq!(longest_example(&states("2U.L1E")), None);
+        assert_eq!(longest_example(&states("1(1N,.A)")), None);
+        assert_eq!(longest_example(&states("1(2N,.A1'E)")).as_deref(), Some("00"));
+        assert_eq!(shortest_example(&states("1P1C1[a-f]")).as_deref(), Some("-\ta"));
+
+        // Whatever comes out has to match.
+        for text in ["1.3(1A,1N1\".\")", "2(1\"ab\"i,1N)1.2(.2A1N)", "1(2N,3A)", "1U1.3L"] {
+            let pattern = Builder::init_default(text.to_string()).set_extended_syntax(true).build().unwrap();
+            for example in [shortest_example(pattern.get_states()), longest_example(pattern.get_states())] {
+                assert!(pattern.is_match(&example.unwrap()).unwrap(), "{}", text);
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use super::pattern::Pattern;
+use super::rule::{PatternType, State};
+
+// Describes states in plain English for people who don't read M patterns. Things one after the other go on one line,
+// joined with "then". Alternatives and repeated groups turn into an indented list under a line saying what to do with
+// them.
+
+/// Either a phrase that fits in a sentence, or a heading with a list under it, each entry its own lines.
+enum Item {
+    Phrase(String),
+    List(String, Vec<Vec<String>>),
+}
+
+/// "exactly 3", "1 to 2", "at least 1" and so on, with "one" and "at most one" for the singular ones.
+fn count_phrase(min: usize, max: Option<usize>) -> String {
+    match (min, max) {
+        (1, Some(1)) => "one".to_string(),
+        (0, Some(1)) => "at most one".to_string(),
+        (min, Some(max)) if min == max => format!("exactly {}", min),
+        (0, None) => "any number of".to_string(),
+        (0, Some(max)) => format!("up to {}", max),
+        (min, None) => format!("at least {}", min),
+        (min, Some(max)) => format!("{} to {}", min, max),
+    }
+}
+
+/// Same as count_phrase, but as how many times something happens.
+fn times_phrase(min: usize, max: Option<usize>) -> String {
+    match (min, max) {
+        (1, Some(1)) => "once".to_string(),
+        (0, Some(1)) => "at most once".to_string(),
+        (0, None) => "any number of times".to_string(),
+        _ => format!("{} times", count_phrase(min, max)),
+    }
+}
+
+fn quoted(text: &str, fold_case: bool) -> String {
+    format!("'{}'{}", text, if fold_case { " (any case)" } else { "" })
+}
+
+/// What a pattern type takes, one character's worth, either singular or plural.
+fn noun(pattern: &PatternType, plural: bool, fold_case: bool) -> String {
+    let (one, many) = match pattern {
+        PatternType::Alphabetic => ("letter", "letters"),
+        PatternType::Uppercase => ("uppercase letter", "uppercase letters"),
+        PatternType::Lowercase => ("lowercase letter", "lowercase letters"),
+        PatternType::Numeric => ("digit", "digits"),
+        PatternType::Punctuation => ("punctuation character", "punctuation characters"),
+        PatternType::Control => ("control character", "control characters"),
+        PatternType::Everything => ("character", "characters"),
+        PatternType::Specific(c) => return quoted(&c.to_string(), fold_case),
+        PatternType::Literal(text) => return quoted(text, fold_case),
+        _ => {
+            let kind = if plural { "characters" } else { "character" };
+            return match pattern {
+                PatternType::Custom(code) => format!("{} from code {}", kind, code),
+                PatternType::Range(low, high) => format!("{} from '{}' to '{}'", kind, low, high),
+                PatternType::Set(ranges) => {
+                    let ranges: Vec<String> = ranges.iter().map(|(low, high)| if low == high { format!("'{}'", low) } else { format!("'{}' to '{}'", low, high) }).collect();
+                    format!("{} from {}", kind, ranges.join(" or "))
+                }
+                PatternType::Not(inner) => format!("{} other than {}", kind, noun(inner, true, fold_case)),
+                PatternType::AnyOf(patterns) => patterns.iter().map(|p| noun(p, plural, fold_case)).collect::<Vec<String>>().join(" or "),
+                PatternType::Predicate(predicate) => format!("{} {}", predicate.get_name(), kind),
+                _ => unreachable!(),
+            };
+        }
+    };
+    if plural { many } else { one }.to_string()
+}
+
+fn plain_phrase(state: &State) -> String {
+    let (min, max) = (state.get_min(), state.get_max());
+    let fold_case = state.is_case_insensitive();
+    match state.get_patterns().as_slice() {
+        // Characters and literals read better as the thing itself and how many times.
+        [pattern @ (PatternType::Specific(_) | PatternType::Literal(_))] => match (min, max) {
+            (1, Some(1)) => noun(pattern, false, fold_case),
+            _ => format!("{} {}", noun(pattern, false, fold_case), times_phrase(min, max)),
+        },
+        [] => "nothing".to_string(),
+        patterns => {
+            let plural = max != Some(1);
+            let nouns: Vec<String> = patterns.iter().map(|p| noun(p, plural, fold_case)).collect();
+            format!("{} {}", count_phrase(min, max), nouns.join(" or "))
+        }
+    }
+}
+
+fn items(state: &State) -> Vec<Item> {
+    let (min, max) = (state.get_min(), state.get_max());
+    let Some(states) = state.get_block_states() else {
+        return vec![Item::Phrase(plain_phrase(state))];
+    };
+    let once = (min, max) == (1, Some(1));
+
+    if state.is_coalesce() {
+        let heading = if once { "one of".to_string() } else { format!("one of these, the same one {}", times_phrase(min, max)) };
+        return vec![Item::List(heading, states.iter().map(|s| lines(std::slice::from_ref(s))).collect())];
+    }
+
+    match (states.as_slice(), state.get_group_options()) {
+        ([], _) => vec![Item::Phrase("nothing".to_string())],
+        _ if once => states.iter().flat_map(items).collect(),
+        // The Builder's group shape, where every repetition picks its own option.
+        (_, Some(options)) => {
+            vec![Item::List(format!("{}, one of these each time", times_phrase(min, max)), options.iter().map(|s| lines(std::slice::from_ref(s))).collect())]
+        }
+        _ => vec![Item::List(times_phrase(min, max), vec![lines(states)])],
+    }
+}
+
+/// The states one after the other, as lines of text with the lists already indented.
+fn lines(states: &[State]) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut open = false; // Whether the last line is a sentence that can keep going.
+    for (i, item) in states.iter().flat_map(items).enumerate() {
+        let then = if i == 0 { "" } else { "then " };
+        match item {
+            Item::Phrase(phrase) if open => {
+                let last = lines.last_mut().unwrap();
+                last.push_str(", then ");
+                last.push_str(&phrase);
+            }
+            Item::Phrase(phrase) => {
+                lines.push(format!("{}{}", then, phrase));
+                open = true;
+            }
+            Item::List(heading, entries) => {
+                lines.push(format!("{}{}:", then, heading));
+                for entry in entries {
+                    for (j, line) in entry.into_iter().enumerate() {
+                        lines.push(format!("{}{}", if j == 0 { "  - " } else { "    " }, line));
+                    }
+                }
+                open = false;
+            }
+        }
+    }
+
+    if lines.is_empty() {
+        lines.push("nothing".to_string());
+    }
+    lines
+}
+
+impl State {
+    /// The state in plain English, one line per sentence, with alternatives and groups as indented lists.
+    pub fn explain(&self) -> String {
+        lines(std::slice::from_ref(self)).join("\n")
+    }
+}
+
+impl Pattern {
+    /// The whole pattern in plain English, the same way State::explain does it.
+    pub fn explain(&self) -> String {
+        let lines = lines(self.get_states());
+        if self.is_negated() {
+            let indented: Vec<String> = lines.iter().map(|line| format!("  {}", line)).collect();
+            format!("anything except:\n{}", indented.join("\n"))
+        } else {
+            lines.join("\n")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex::builder::Builder;
+
+    fn explain(text: &str) -> String {
+        Builder::init_default(text.to_string()).set_extended_syntax(true).build().unwrap().explain()
+    }
+
+    #[test]
+    fn explains_in_plain_english() {
+        assert_eq!(explain("3N1\"-\"2N1\"-\"4N"), "exactly 3 digits, then '-', then exactly 2 digits, then '-', then exactly 4 digits");
+        assert_eq!(explain("1U.L1.3AN0.1\"x\"i"), "one uppercase letter, then any number of lowercase letters, then 1 to 3 letters or digits, then 'x' (any case) at most once");
+        assert_eq!(explain("1[a-f0-9]2'N1E"), "one character from '0' to '9' or 'a' to 'f', then exactly 2 characters other than digits, then one character");
+        assert_eq!(explain("1(1\"Y\",1\"N\")1.2N"), "one of:\n  - 'Y'\n  - 'N'\nthen 1 to 2 digits");
+        assert_eq!(explain("'3N"), "anything except:\n  exactly 3 digits");
+    }
+
+    #[test]
+    fn nests_groups_as_lists() {
+        let expected = [
+            "one letter",
+            "then 1 to 3 times, one of these each time:",
+            "  - one digit, then '.'",
+            "  - one of:",
+            "      - 'ab'",
+            "      - at least 2 punctuation characters",
+            "then one digit",
+        ];
+        assert_eq!(explain("1A1.3(1N1\".\",1(1\"ab\",2.P))1N"), expected.join("\n"));
+    }
+}
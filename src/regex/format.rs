@@ -0,0 +1,142 @@
+use super::builder::Builder;
+use super::pattern::Pattern;
+use super::rule::State;
+
+// Canonical formatting for pattern text. The text goes through the Builder, gets a few meaning preserving rewrites,
+// and comes back out through the printer, which already settles how counts are written (`1N` not `1.1N`, `.N` not
+// `0.N`). On top of that, adjacent literals with exact counts merge into one, and groups with a single option lose
+// their parentheses whenever the counts allow it.
+
+/// Formats pattern text with the default Builder settings.
+pub fn canonicalize(text: &str) -> Result<String, &'static str> {
+    canonicalize_with(&Builder::init_default(text.to_string()))
+}
+
+/// Formats whatever pattern text the builder was given, so dialect and syntax options carry over.
+pub fn canonicalize_with(builder: &Builder) -> Result<String, &'static str> {
+    let pattern = builder.build()?;
+    let canonical = Pattern::new(simplify_sequence(pattern.get_states().clone()));
+    Ok(if pattern.is_negated() { canonical.negate() } else { canonical }.to_string())
+}
+
+pub fn is_canonical(text: &str) -> Result<bool, &'static str> {
+    Ok(canonicalize(text)? == text)
+}
+
+fn simplify_sequence(states: Vec<State>) -> Vec<State> {
+    let mut output = Vec::new();
+    for state in states {
+        for simplified in simplify_state(state) {
+            push_merging_literals(&mut output, simplified);
+        }
+    }
+    output
+}
+
+/// The sequence an option stands for. The Builder wraps sequences longer than one state in a plain block.
+fn option_sequence(option: State) -> Vec<State> {
+    let is_wrapper = option.is_block_type()
+        && !option.is_coalesce()
+        && option.get_group_options().is_none()
+        && option.get_min() == 1
+        && option.get_max() == Some(1);
+    if is_wrapper {
+        option.expand_block_states().unwrap()
+    } else {
+        vec![option]
+    }
+}
+
+fn simplify_state(state: State) -> Vec<State> {
+    let options = match state.get_group_options() {
+        None => return vec![state],
+        Some(options) => options.clone(),
+    };
+    let (min, max) = (state.get_min(), state.get_max());
+    let mut options: Vec<Vec<State>> = options.into_iter().map(|o| simplify_sequence(option_sequence(o))).collect();
+
+    if options.len() == 1 {
+        let only = options.pop().unwrap();
+        if min == 1 && max == Some(1) {
+            return only;
+        }
+        if let [single] = only.as_slice() {
+            if let Some(folded) = single.fold_counts(min, max) {
+                return vec![folded];
+            }
+        }
+        options.push(only);
+    }
+
+    let rebuilt = options.into_iter().map(State::new_option).collect();
+    vec![State::new_group(min, max, rebuilt).set_identifier(state.get_identifier())]
+}
+
+fn push_merging_literals(output: &mut Vec<State>, state: State) {
+    if let (Some(previous), Some(text)) = (output.last(), exact_literal(&state)) {
+        if let Some(previous_text) = exact_literal(previous) {
+            if previous.is_case_insensitive() == state.is_case_insensitive() {
+                let merged = State::new_literal(1, Some(1), &(previous_text + &text))
+                    .set_case_insensitive(state.is_case_insensitive())
+                    .set_classification(state.get_classification());
+                output.pop();
+                output.push(merged);
+                return;
+            }
+        }
+    }
+    output.push(state);
+}
+
+/// The text a literal with an exact count stands for, written out.
+fn exact_literal(state: &State) -> Option<String> {
+    let text = state.get_literal()?;
+    let count = state.get_max().filter(|max| *max == state.get_min())?;
+    Some(text.repeat(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_forms() {
+        let cases = [
+            ("1.1N", "1N"),
+            ("0.N", ".N"),
+            ("0.3N1.N", ".3N1.N"),
+            ("1\"A\"1\"B\"2\"C\"", "1\"ABCC\""),
+            ("1\"A\".2\"B\"", "1\"A\".2\"B\""),
+            ("1(1N)", "1N"),
+            ("2(1\"AB\")", "2\"AB\""),
+            ("2(3N)", "6N"),
+            ("2.3(5N)", "2.3(5N)"),
+            ("2.3(1.2N)", "2.6N"),
+            ("2(.N)", ".N"),
+            ("1(1\"A\"1N)1\"B\"", "1\"A\"1N1\"B\""),
+            ("1\"X\"1(1\"Y\"1N)", "1\"XY\"1N"),
+            ("2(1(1A,1N))", "2(1A,1N)"),
+            ("1(1\"a\"1\"b\",1N)", "1(1\"ab\",1N)"),
+            ("'1.1A", "'1A"),
+            ("3N1\"-\"2N", "3N1\"-\"2N"),
+        ];
+        for (text, canonical) in cases {
+            assert_eq!(canonicalize(text).unwrap(), canonical, "formatting {}", text);
+            assert!(is_canonical(canonical).unwrap(), "{} should already be canonical", canonical);
+        }
+        assert!(!is_canonical("1.1N").unwrap());
+        assert!(canonicalize("1(").is_err());
+    }
+
+    #[test]
+    fn formatting_keeps_meaning() {
+        let inputs = ["", "AB", "ABAB", "5", "A5", "Y", "YN", "12345", "1234567890", "ABC"];
+        for text in ["1\"A\"1\"B\"", "2(1\"AB\")", "2(3N)", "1.2(1(1\"A\",1\"B\"))", "1(1\"A\"1\"B\",1N)", "2.3(1.2N)", "2(.N)"] {
+            let original = Builder::init_default(text.to_string()).build().unwrap();
+            let formatted = Builder::init_default(canonicalize(text).unwrap()).build().unwrap();
+            for input in inputs {
+                assert_eq!(original.is_match(input).unwrap(), formatted.is_match(input).unwrap(), "{} on {:?}", text, input);
+            }
+        }
+    }
+}
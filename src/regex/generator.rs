@@ -0,0 +1,251 @@
+use super::alphabet::CharClass;
+use super::helpers::case_variants;
+use super::pattern::Pattern;
+use super::program::Program;
+use super::rule::State;
+use crate::DEFAULT_ENGINE_TYPICAL_MAX;
+
+/// How many times to try before giving up on a string, since a random pick can land on an option with nothing to give.
+const ATTEMPTS: usize = 64;
+
+/// SplitMix64. Small and plenty random for test data, and the same seed always gives the same strings.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Something in `0..bound`. The bound can't be zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+/// Makes up random strings for a State tree, either ones that match or, in near miss mode, ones that almost do.
+#[derive(Clone, Debug)]
+pub struct Generator {
+    rng: Rng,
+    repeat_cap: usize,
+    weights: [u32; 7], // Indexed by CharClass.
+    near_miss: bool,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            repeat_cap: DEFAULT_ENGINE_TYPICAL_MAX,
+            weights: [4, 4, 1, 4, 2, 0, 0],
+            near_miss: false,
+        }
+    }
+
+    /// How many repetitions past the minimum an unbounded state can go.
+    pub fn set_repeat_cap(mut self, cap: usize) -> Self {
+        self.repeat_cap = cap;
+        self
+    }
+
+    /// How often a character of the class comes up when a state takes more than one class, like E or a mix of codes.
+    /// Control characters and anything past ASCII are off unless they get a weight. A state that only takes classes
+    /// weighted zero still gets its characters, picked evenly.
+    pub fn set_weight(mut self, class: CharClass, weight: u32) -> Self {
+        self.weights[class as usize] = weight;
+        self
+    }
+
+    /// Makes strings that fail the pattern, each one a small edit away from one that matches.
+    pub fn set_near_miss(mut self, near_miss: bool) -> Self {
+        self.near_miss = near_miss;
+        self
+    }
+
+    /// The next string for the states, or None if there's no such string to be found. Some patterns take every
+    /// string there is, so they have no near misses.
+    pub fn generate(&mut self, states: &[State]) -> Option<String> {
+        if self.near_miss {
+            self.near_miss(states)
+        } else {
+            (0..ATTEMPTS).find_map(|_| self.matching(states)).map(|chars| chars.into_iter().map(|(c, _)| c).collect())
+        }
+    }
+
+    /// Same as generate, except a negated pattern swaps what counts as a match and a near miss.
+    pub fn generate_for(&mut self, pattern: &Pattern) -> Option<String> {
+        let near_miss = self.near_miss;
+        self.near_miss ^= pattern.is_negated();
+        let generated = self.generate(pattern.get_states());
+        self.near_miss = near_miss;
+        generated
+    }
+
+    /// A matching string, with the plain state each character came from.
+    fn matching<'s>(&mut self, states: &'s [State]) -> Option<Vec<(char, &'s State)>> {
+        let mut chars = Vec::new();
+        for state in states {
+            self.walk(state, &mut chars)?;
+        }
+        Some(chars)
+    }
+
+    fn walk<'s>(&mut self, state: &'s State, chars: &mut Vec<(char, &'s State)>) -> Option<()> {
+        let times = self.repetitions(state);
+        match state.get_block_states() {
+            Some(options) if state.is_coalesce() => {
+                if times > 0 {
+                    let option = options.get(self.rng.below(options.len().max(1) as u64) as usize)?;
+                    for _ in 0..times {
+                        self.walk(option, chars)?;
+                    }
+                }
+            }
+            Some(states) => {
+                for _ in 0..times {
+                    for s in states {
+                        self.walk(s, chars)?;
+                    }
+                }
+            }
+            None => match state.get_literal() {
+                Some(text) => {
+                    for c in std::iter::repeat_n(text.chars(), times).flatten() {
+                        let variants = if state.is_case_insensitive() { case_variants(c) } else { vec![c] };
+                        chars.push((variants[self.rng.below(variants.len() as u64) as usize], state));
+                    }
+                }
+                None if times > 0 => {
+                    let candidates: Vec<char> = candidates().filter(|c| state.does_char_qualify(*c)).collect();
+                    for _ in 0..times {
+                        chars.push((self.pick(&candidates)?, state));
+                    }
+                }
+                None => {}
+            },
+        }
+        Some(())
+    }
+
+    fn repetitions(&mut self, state: &State) -> usize {
+        let min = state.get_min();
+        let spread = state.get_max().map_or(self.repeat_cap, |max| max.saturating_sub(min));
+        min + self.rng.below(spread as u64 + 1) as usize
+    }
+
+    /// A random character out of the candidates, picking the class by weight first.
+    fn pick(&mut self, candidates: &[char]) -> Option<char> {
+        let mut groups: Vec<(u32, Vec<char>)> = CharClass::ALL.iter().map(|class| (self.weights[*class as usize], Vec::new())).collect();
+        for c in candidates {
+            groups[CharClass::of(*c) as usize].1.push(*c);
+        }
+        groups.retain(|(_, chars)| !chars.is_empty());
+        if groups.iter().all(|(weight, _)| *weight == 0) {
+            groups.iter_mut().for_each(|(weight, _)| *weight = 1);
+        }
+
+        let mut roll = self.rng.below(groups.iter().map(|(weight, _)| *weight as u64).sum::<u64>().max(1));
+        let (_, chars) = groups.iter().find(|(weight, _)| {
+            let here = roll < *weight as u64;
+            roll = roll.saturating_sub(*weight as u64);
+            here
+        })?;
+        Some(chars[self.rng.below(chars.len() as u64) as usize])
+    }
+
+    /// Takes a matching string and breaks it one way or another: a character the state wouldn't take, one too few, or
+    /// one too many. Edits that still match get thrown out.
+    fn near_miss(&mut self, states: &[State]) -> Option<String> {
+        let program = Program::compile(states);
+        for _ in 0..ATTEMPTS {
+            let Some(chars) = self.matching(states) else { continue };
+            let mut text: Vec<char> = chars.iter().map(|(c, _)| *c).collect();
+            let at = self.rng.below(text.len() as u64 + 1) as usize;
+            match self.rng.below(3) {
+                0 if at < text.len() => {
+                    let (original, state) = chars[at];
+                    let rejected: Vec<char> = match state.get_literal() {
+                        Some(_) => candidates().filter(|c| *c != original && !(state.is_case_insensitive() && case_variants(original).contains(c))).collect(),
+                        None => candidates().filter(|c| !state.does_char_qualify(*c)).collect(),
+                    };
+                    let Some(replacement) = self.pick(&rejected) else { continue };
+                    text[at] = replacement;
+                }
+                1 if at < text.len() => {
+                    text.remove(at);
+                }
+                _ => {
+                    let Some(extra) = self.pick(&candidates().collect::<Vec<char>>()) else { continue };
+                    text.insert(at, extra);
+                }
+            }
+
+            let text: String = text.into_iter().collect();
+            if !program.is_match(&text) {
+                return Some(text);
+            }
+        }
+        None
+    }
+}
+
+/// The characters strings get made of: ASCII, Latin-1 and a little past it, which is enough to hit every class.
+fn candidates() -> impl Iterator<Item = char> {
+    ('\u{0}'..'\u{250}').chain(['\u{3a9}', '\u{3c9}', '\u{416}', '\u{436}', '\u{663}', '\u{2014}', '\u{20ac}', '\u{4e2d}'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::testing::compile;
+
+    #[test]
+    fn generates_matches_and_near_misses() {
+        let texts = ["3N1\"-\"2N1\"-\"4N", "1.3(1A,1N1\".\")", "1.(2N,1\"x\"1A)1\"!\"", "2(1\"ab\"i,1N)1.2(.2A1N)", "1U.L", "1\"Y\"1.2N", "3E"];
+        let mut generator = Generator::new(7);
+        let mut near = Generator::new(7).set_near_miss(true);
+        for text in texts {
+            let pattern = compile(text);
+            for _ in 0..50 {
+                let generated = generator.generate_for(&pattern).unwrap();
+                assert!(pattern.is_match(&generated).unwrap(), "{} gave {:?}", text, generated);
+                let missed = near.generate_for(&pattern).unwrap();
+                assert!(!pattern.is_match(&missed).unwrap(), "{} gave {:?}", text, missed);
+            }
+        }
+
+        // Negated patterns swap the two around, and E matches everything so there's nothing to miss.
+        let negated = compile("3N").negate();
+        assert!(negated.is_match(&generator.generate_for(&negated).unwrap()).unwrap());
+        assert_eq!(near.generate_for(&compile(".E")), None);
+    }
+
+    #[test]
+    fn seeds_caps_and_weights() {
+        let states = compile(".E").get_states().clone();
+        let run = |generator: Generator| {
+            let mut generator = generator;
+            (0..20).map(|_| generator.generate(&states).unwrap()).collect::<Vec<String>>()
+        };
+        assert_eq!(run(Generator::new(42)), run(Generator::new(42)));
+        assert_ne!(run(Generator::new(42)), run(Generator::new(43)));
+
+        let strings = run(Generator::new(1).set_repeat_cap(3));
+        assert!(strings.iter().all(|s| s.chars().count() <= 3));
+        assert!(strings.iter().flat_map(|s| s.chars()).all(|c| !c.is_control() && c.is_ascii()));
+
+        let weights = CharClass::ALL.iter().fold(Generator::new(1), |generator, class| generator.set_weight(*class, 0));
+        let strings = run(weights.set_weight(CharClass::Punctuation, 1));
+        assert!(strings.iter().flat_map(|s| s.chars()).all(|c| c.is_ascii_punctuation() || c == ' '));
+    }
+}
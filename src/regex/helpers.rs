@@ -1,22 +1,120 @@
 use crate::regex::rule::State;
 /// Used by the engine to cache where it is at a certain point in the algorithm.
 #[derive(Clone)]
-pub struct Cache {
-    counts: Vec<usize>,
-    cur_pos: usize,
-    states: Vec<State>,
-}
-
-impl Cache {
-    pub fn new(counts: Vec<usize>, cur_pos: usize, states: Vec<State>) -> Self {
-        Self {
-            counts,
-            cur_pos,
-            states,
+pub enum Cache {
+    /// How far the undo log and counts went, and where the cursor was. Constant size no matter how big the states get.
+    Checkpoint {
+        counts_len: usize,
+        last_count: usize,
+        cur_pos: usize,
+        log_len: usize,
+    },
+    /// A full copy of everything, which is how the engine used to back up. Only around to benchmark against.
+    #[cfg(any(test, feature = "bench"))]
+    Snapshot {
+        counts: Vec<usize>,
+        cur_pos: usize,
+        states: Vec<State>,
+        log_len: usize,
+    },
+}
+
+/// Every change the engine made to its state vector, newest last, so it can be rewound to any checkpoint. Each entry
+/// says that `inserted` states went in at `index` where `removed` used to be.
+#[derive(Default)]
+pub struct UndoLog {
+    entries: Vec<(usize, usize, State)>,
+}
+
+impl UndoLog {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn record(&mut self, index: usize, inserted: usize, removed: State) {
+        self.entries.push((index, inserted, removed));
+    }
+
+    pub fn entries(&self) -> &[(usize, usize, State)] {
+        &self.entries
+    }
+
+    /// Drops changes until only the first `len` are left, without undoing them.
+    #[cfg(any(test, feature = "bench"))]
+    pub fn forget(&mut self, len: usize) {
+        self.entries.truncate(len);
+    }
+
+    /// Undoes changes until only the first `len` are left.
+    pub fn rewind(&mut self, states: &mut Vec<State>, len: usize) {
+        while self.entries.len() > len {
+            let (index, inserted, removed) = self.entries.pop().unwrap();
+            states.splice(index..index + inserted, std::iter::once(removed));
         }
     }
+}
+
+/// Length bounds of states one after the other. Upper limits that overflow count as no limit.
+pub fn sequence_length_bounds(states: &[State]) -> (usize, Option<usize>) {
+    states.iter().fold((0, Some(0)), |(low, high), state| {
+        let (state_low, state_high) = state.length_bounds();
+        (low.saturating_add(state_low), high.zip(state_high).and_then(|(a, b)| a.checked_add(b)))
+    })
+}
 
-    pub fn extract(self) -> (Vec<usize>, usize, Vec<State>) {
-        (self.counts, self.cur_pos, self.states)
+/// Length bounds of something with the given bounds, repeated min to max times.
+pub fn repeat_bounds((low, high): (usize, Option<usize>), min: usize, max: Option<usize>) -> (usize, Option<usize>) {
+    let high = match (high, max) {
+        (Some(0), _) | (_, Some(0)) => Some(0),
+        (Some(high), Some(max)) => high.checked_mul(max),
+        _ => None,
+    };
+    (low.saturating_mul(min), high)
+}
+
+/// Sorts and merges inclusive character ranges so they can be binary searched.
+pub fn normalize_ranges(mut ranges: Vec<(char, char)>) -> Vec<(char, char)> {
+    ranges.retain(|(low, high)| low <= high);
+    ranges.sort();
+    let mut output: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+    for (low, high) in ranges {
+        match output.last_mut() {
+            Some(last) if (low as u32) <= (last.1 as u32).saturating_add(1) => last.1 = last.1.max(high),
+            _ => output.push((low, high)),
+        }
     }
-}
\ No newline at end of file
+    output
+}
+
+/// Binary search over ranges that went through normalize_ranges.
+pub fn ranges_contain(ranges: &[(char, char)], character: char) -> bool {
+    ranges
+        .binary_search_by(|(low, high)| {
+            if *high < character {
+                std::cmp::Ordering::Less
+            } else if *low > character {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// The character along with its lower and upper case forms, where those are a single character.
+pub fn case_variants(character: char) -> Vec<char> {
+    let mut output = vec![character];
+    let mut lower = character.to_lowercase();
+    let mut upper = character.to_uppercase();
+    let singles = [lower.next().filter(|_| lower.next().is_none()), upper.next().filter(|_| upper.next().is_none())];
+    for c in singles.into_iter().flatten() {
+        if !output.contains(&c) {
+            output.push(c);
+        }
+    }
+    output
+}
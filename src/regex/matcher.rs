@@ -0,0 +1,28 @@
+use super::pattern::Pattern;
+
+/// Anything that can say whether a string matches, whether it was compiled from pattern text or put together out of
+/// other patterns.
+pub trait Matcher {
+    fn is_match(&self, input: &str) -> Result<bool, &'static str>;
+
+    /// How many of the inputs match.
+    fn count_matches<'a, I>(&self, inputs: I) -> Result<usize, &'static str>
+    where
+        I: IntoIterator<Item = &'a str>,
+        Self: Sized,
+    {
+        let mut count = 0;
+        for input in inputs {
+            if self.is_match(input)? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+impl Matcher for Pattern {
+    fn is_match(&self, input: &str) -> Result<bool, &'static str> {
+        Pattern::is_match(self, input)
+    }
+}
@@ -1,4 +1,21 @@
 pub mod engine;
 pub mod rule;
 mod helpers;
-pub mod builder;
\ No newline at end of file
+pub mod builder;
+pub mod pattern;
+pub mod dialect;
+pub mod table;
+pub mod printer;
+pub mod format;
+pub mod optimize;
+pub mod program;
+pub mod natural;
+pub mod alphabet;
+pub mod automaton;
+pub mod generator;
+pub mod example;
+pub mod matcher;
+pub mod combination;
+pub mod explain;
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A whole number that never overflows, for counts that get too big for any integer type. Only as much arithmetic as
+/// counting strings needs.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Natural {
+    digits: Vec<u32>, // Base 2^32, least significant first, no zeros on the end. Zero is empty.
+}
+
+impl Natural {
+    pub fn zero() -> Self {
+        Self::default()
+    }
+
+    pub fn one() -> Self {
+        Self::from(1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    /// The value as a u128, if it fits.
+    pub fn to_u128(&self) -> Option<u128> {
+        if self.digits.len() > 4 {
+            return None;
+        }
+        Some(self.digits.iter().rev().fold(0, |value, digit| (value << 32) | *digit as u128))
+    }
+
+    pub fn add(&self, other: &Natural) -> Natural {
+        let mut sum = self.clone();
+        sum.add_assign(other);
+        sum
+    }
+
+    pub fn add_assign(&mut self, other: &Natural) {
+        if self.digits.len() < other.digits.len() {
+            self.digits.resize(other.digits.len(), 0);
+        }
+        let mut carry = 0u64;
+        for (i, digit) in self.digits.iter_mut().enumerate() {
+            let total = *digit as u64 + other.digits.get(i).copied().unwrap_or(0) as u64 + carry;
+            *digit = total as u32;
+            carry = total >> 32;
+            if carry == 0 && i >= other.digits.len() {
+                break;
+            }
+        }
+        if carry > 0 {
+            self.digits.push(carry as u32);
+        }
+    }
+
+    /// `self - other`, or None if that would go below zero.
+    pub fn checked_sub(&self, other: &Natural) -> Option<Natural> {
+        if *self < *other {
+            return None;
+        }
+        let mut digits = self.digits.clone();
+        let mut borrow = 0i64;
+        for (i, digit) in digits.iter_mut().enumerate() {
+            let total = *digit as i64 - other.digits.get(i).copied().unwrap_or(0) as i64 - borrow;
+            borrow = if total < 0 { 1 } else { 0 };
+            *digit = (total + (borrow << 32)) as u32;
+        }
+        Some(Self::trimmed(digits))
+    }
+
+    pub fn mul_small(&self, factor: u64) -> Natural {
+        let (low, high) = (factor as u32, (factor >> 32) as u32);
+        let mut product = self.mul_digit(low);
+        if high != 0 {
+            let mut shifted = self.mul_digit(high);
+            if !shifted.is_zero() {
+                shifted.digits.insert(0, 0);
+            }
+            product.add_assign(&shifted);
+        }
+        product
+    }
+
+    pub fn mul(&self, other: &Natural) -> Natural {
+        let mut digits = vec![0u32; self.digits.len() + other.digits.len()];
+        for (i, a) in self.digits.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, b) in other.digits.iter().enumerate() {
+                let total = digits[i + j] as u64 + *a as u64 * *b as u64 + carry;
+                digits[i + j] = total as u32;
+                carry = total >> 32;
+            }
+            digits[i + other.digits.len()] = carry as u32;
+        }
+        Self::trimmed(digits)
+    }
+
+    pub fn pow(&self, exponent: usize) -> Natural {
+        (0..exponent).fold(Self::one(), |power, _| power.mul(self))
+    }
+
+    fn mul_digit(&self, factor: u32) -> Natural {
+        let mut carry = 0u64;
+        let mut digits: Vec<u32> = self.digits.iter().map(|digit| {
+            let total = *digit as u64 * factor as u64 + carry;
+            carry = total >> 32;
+            total as u32
+        }).collect();
+        digits.push(carry as u32);
+        Self::trimmed(digits)
+    }
+
+    /// Divides in place by something small, handing back the remainder.
+    fn div_small(&mut self, divisor: u32) -> u32 {
+        let mut remainder = 0u64;
+        for digit in self.digits.iter_mut().rev() {
+            let total = (remainder << 32) | *digit as u64;
+            *digit = (total / divisor as u64) as u32;
+            remainder = total % divisor as u64;
+        }
+        *self = Self::trimmed(std::mem::take(&mut self.digits));
+        remainder as u32
+    }
+
+    fn trimmed(mut digits: Vec<u32>) -> Natural {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        Natural { digits }
+    }
+}
+
+impl From<u64> for Natural {
+    fn from(value: u64) -> Self {
+        Self::trimmed(vec![value as u32, (value >> 32) as u32])
+    }
+}
+
+impl Ord for Natural {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.digits.len().cmp(&other.digits.len()).then_with(|| self.digits.iter().rev().cmp(other.digits.iter().rev()))
+    }
+}
+
+impl PartialOrd for Natural {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Natural {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Nine decimal digits at a time, then pad every chunk but the first.
+        let mut rest = self.clone();
+        let mut chunks = Vec::new();
+        while !rest.is_zero() {
+            chunks.push(rest.div_small(1_000_000_000));
+        }
+        let mut text = chunks.pop().unwrap_or(0).to_string();
+        for chunk in chunks.iter().rev() {
+            text.push_str(&format!("{:09}", chunk));
+        }
+        f.pad(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let big = Natural::from(u64::MAX);
+        assert_eq!(big.add(&Natural::one()).to_u128(), Some(u64::MAX as u128 + 1));
+        assert_eq!(big.mul_small(u64::MAX).to_u128(), Some(u64::MAX as u128 * u64::MAX as u128));
+        assert_eq!(big.mul(&big), big.mul_small(u64::MAX));
+        assert_eq!(big.mul(&big).checked_sub(&big.mul_small(2)).unwrap().add(&big.mul_small(2)), big.mul(&big));
+        assert_eq!(Natural::one().checked_sub(&big), None);
+        assert!(Natural::from(10).pow(40) > Natural::from(u64::MAX).pow(2));
+        assert_eq!(Natural::from(10).pow(40).to_u128(), None);
+    }
+
+    #[test]
+    fn prints_in_decimal() {
+        assert_eq!(Natural::zero().to_string(), "0");
+        assert_eq!(Natural::from(1_000_000_007).to_string(), "1000000007");
+        assert_eq!(Natural::from(10).pow(30).to_string(), format!("1{}", "0".repeat(30)));
+        assert_eq!(Natural::from(26).pow(30).to_string(), "2813198901284745919258621029615971520741376");
+    }
+}
@@ -0,0 +1,256 @@
+use super::rule::State;
+
+// Meaning preserving cleanup for state trees, mostly aimed at hand-built ones like the Builder's `default_engine`.
+// Plain blocks that only repeat once get spliced into their parent, blocks around a single state hand their count to
+// that state when the counts multiply cleanly, adjacent states that repeat the same thing merge (`1N1N` is `2N`), and
+// states every option of a coalesce starts with get pulled out in front of it.
+//
+// Anything whose identifier is in the keep list stays where it is with the span it had, so captures still line up.
+// Everything else can lose its identifier or end up sharing one with whatever it merged into.
+
+/// Optimizes a sequence of states. `keep` lists the identifiers that have to survive untouched.
+pub fn optimize(states: Vec<State>, keep: &[usize]) -> Vec<State> {
+    let mut output = Vec::new();
+    for state in states {
+        for optimized in optimize_state(state, keep) {
+            push_merging(&mut output, optimized, keep);
+        }
+    }
+    output
+}
+
+fn is_kept(state: &State, keep: &[usize]) -> bool {
+    state.get_identifier() != 0 && keep.contains(&state.get_identifier())
+}
+
+/// The sequence a state turns into. Usually just the state again, but blocks can dissolve into their parent.
+fn optimize_state(state: State, keep: &[usize]) -> Vec<State> {
+    let children = match state.get_block_states() {
+        None => return vec![state],
+        Some(children) => children.clone(),
+    };
+    let (min, max) = (state.get_min(), state.get_max());
+
+    if state.is_coalesce() {
+        let options: Vec<Vec<State>> = children.into_iter().map(|o| optimize_state(o, keep)).collect();
+        return optimize_coalesce(state, options, keep);
+    }
+
+    let children = optimize(children, keep);
+    if is_kept(&state, keep) {
+        return vec![rebuild_block(&state, children, false)];
+    }
+    if children.is_empty() || (min == 1 && max == Some(1)) {
+        return children;
+    }
+    // A kept state has to keep its own span, so it can't take the block's count.
+    if let [only] = children.as_slice() {
+        if let Some(folded) = only.fold_counts(min, max).filter(|_| !is_kept(only, keep)) {
+            return vec![folded];
+        }
+    }
+    vec![rebuild_block(&state, children, false)]
+}
+
+fn optimize_coalesce(state: State, mut options: Vec<Vec<State>>, keep: &[usize]) -> Vec<State> {
+    let (min, max) = (state.get_min(), state.get_max());
+    if is_kept(&state, keep) {
+        return vec![rebuild_block(&state, options.into_iter().map(State::new_option).collect(), true)];
+    }
+
+    // With a single option there is nothing to pick, so it's just a block repeating that option.
+    if options.len() == 1 {
+        let only = State::new_block(min, max, vec![State::new_option(options.pop().unwrap())]);
+        return optimize_state(keep_identifier(&state, only), keep);
+    }
+
+    // A coalesce repeating more than once repeats the whole option, so only a single pick can give up its prefix.
+    let mut prefix = Vec::new();
+    if min == 1 && max == Some(1) {
+        while let Some(common) = common_first(&options, keep) {
+            for option in options.iter_mut() {
+                option.remove(0);
+            }
+            prefix.push(common);
+        }
+    }
+
+    if prefix.is_empty() {
+        return vec![rebuild_block(&state, options.into_iter().map(State::new_option).collect(), true)];
+    }
+    let rest = rebuild_block(&state, options.into_iter().map(|o| State::new_option(optimize(o, keep))).collect(), true);
+    let mut output = optimize(prefix, keep);
+    output.extend(optimize_state(rest, keep));
+    output
+}
+
+/// The state every option starts with, if they all start with the same one and none of the copies is being kept.
+fn common_first(options: &[Vec<State>], keep: &[usize]) -> Option<State> {
+    let first = options.first()?.first()?;
+    let all_same = options.iter().all(|o| o.first().is_some_and(|s| same_state(s, first)));
+    let dropped_kept = options[1..].iter().any(|o| is_kept(&o[0], keep) && o[0].get_identifier() != first.get_identifier());
+    if all_same && !dropped_kept {
+        Some(first.clone())
+    } else {
+        None
+    }
+}
+
+fn rebuild_block(original: &State, children: Vec<State>, coalesce: bool) -> State {
+    let (min, max) = (original.get_min(), original.get_max());
+    let block = if coalesce {
+        State::new_coalesce_block(min, max, children)
+    } else {
+        State::new_block(min, max, children)
+    };
+    keep_identifier(original, block)
+}
+
+/// New blocks take over the identifier of the block they replace. Blocks don't classify anything themselves, so their
+/// other flags don't matter.
+fn keep_identifier(original: &State, block: State) -> State {
+    block.set_identifier(original.get_identifier())
+}
+
+/// Pushes a state onto the sequence, merging it into the last one when both repeat the same thing.
+fn push_merging(output: &mut Vec<State>, state: State, keep: &[usize]) {
+    if let Some(previous) = output.last() {
+        let mergeable = !previous.is_coalesce() && !is_kept(previous, keep) && !is_kept(&state, keep) && same_repeatable(previous, &state);
+        if mergeable {
+            let min = previous.get_min().checked_add(state.get_min());
+            let max = match (previous.get_max(), state.get_max()) {
+                (Some(a), Some(b)) => a.checked_add(b).map(Some),
+                _ => Some(None),
+            };
+            if let (Some(min), Some(max)) = (min, max) {
+                let merged = output.pop().unwrap().set_bounds(min, max);
+                output.push(merged);
+                return;
+            }
+        }
+    }
+    output.push(state);
+}
+
+/// Whether two states match the same strings, ignoring identifiers.
+fn same_state(a: &State, b: &State) -> bool {
+    a.get_min() == b.get_min() && a.get_max() == b.get_max() && same_repeatable(a, b)
+}
+
+/// Whether two states repeat the same thing, whatever their counts are. Pattern sets are compared as sets.
+fn same_repeatable(a: &State, b: &State) -> bool {
+    let same_patterns = a.get_patterns().iter().all(|p| b.get_patterns().contains(p)) && b.get_patterns().iter().all(|p| a.get_patterns().contains(p));
+    let same_children = match (a.get_block_states(), b.get_block_states()) {
+        (None, None) => true,
+        (Some(x), Some(y)) => x.len() == y.len() && x.iter().zip(y).all(|(x, y)| same_state(x, y)),
+        _ => false,
+    };
+    let same_flags = a.is_block_type()
+        || (a.get_classification() == b.get_classification()
+            && a.is_case_insensitive() == b.is_case_insensitive()
+            && a.get_pattern_table() == b.get_pattern_table());
+    a.is_coalesce() == b.is_coalesce() && same_flags && same_patterns && same_children
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::builder::Builder;
+    use crate::regex::pattern::Pattern;
+    use crate::regex::rule::PatternType;
+
+    fn numeric(min: usize, max: Option<usize>) -> State {
+        State::new(min, max, vec![PatternType::Numeric])
+    }
+
+    fn printed(states: Vec<State>) -> String {
+        Pattern::new(states).to_string()
+    }
+
+    #[test]
+    fn flattens_and_merges() {
+        assert_eq!(printed(optimize(vec![numeric(1, Some(1)), numeric(1, Some(1))], &[])), "2N");
+        assert_eq!(printed(optimize(vec![numeric(1, Some(2)), numeric(0, None)], &[])), "1.N");
+
+        // Wrappers around wrappers around a sequence, which is what hand-built trees tend to look like.
+        let wrapped = State::new_block(1, Some(1), vec![State::new_block(1, Some(1), vec![
+            numeric(1, Some(1)),
+            State::new(1, Some(1), vec![PatternType::Specific('.')]),
+        ])]);
+        assert_eq!(printed(optimize(vec![wrapped, numeric(1, Some(1))], &[])), "1N1\".\"1N");
+
+        // Counts only fold into the state when every total in between is still reachable.
+        assert_eq!(printed(optimize(vec![State::new_block(0, None, vec![numeric(1, Some(1))])], &[])), ".N");
+        assert_eq!(printed(optimize(vec![State::new_block(1, Some(3), vec![numeric(0, Some(2))])], &[])), ".6N");
+        assert_eq!(printed(optimize(vec![State::new_block(2, Some(2), vec![State::new_literal(3, Some(3), "ab")])], &[])), "6\"ab\"");
+        assert_eq!(printed(optimize(vec![State::new_block(2, Some(3), vec![numeric(5, Some(5))])], &[])), "2.3(5N)");
+
+        // Same pattern set in a different order is still the same state, but a different classification isn't.
+        let an = State::new(1, Some(1), vec![PatternType::Alphabetic, PatternType::Numeric]);
+        let na = State::new(2, Some(2), vec![PatternType::Numeric, PatternType::Alphabetic]);
+        assert_eq!(optimize(vec![an.clone(), na], &[]).len(), 1);
+        let latin1 = an.clone().set_classification(crate::regex::rule::Classification::Latin1);
+        assert_eq!(optimize(vec![an, latin1], &[]).len(), 2);
+    }
+
+    #[test]
+    fn hoists_common_prefixes() {
+        let option = |rest: State| State::new_block(1, Some(1), vec![State::new_literal(1, Some(1), "ID"), numeric(1, Some(1)), rest]);
+        let coalesce = State::new_coalesce_block(1, Some(1), vec![
+            option(State::new(1, Some(1), vec![PatternType::Uppercase])),
+            option(State::new(1, Some(1), vec![PatternType::Lowercase])),
+        ]);
+        assert_eq!(printed(optimize(vec![coalesce], &[])), "1\"ID\"1N1(1U,1L)");
+
+        // Repeating the pick repeats the prefix along with it, so those stay put.
+        let repeated = State::new_coalesce_block(2, Some(2), vec![
+            State::new_block(1, Some(1), vec![numeric(1, Some(1)), State::new_literal(1, Some(1), "a")]),
+            State::new_block(1, Some(1), vec![numeric(1, Some(1)), State::new_literal(1, Some(1), "b")]),
+        ]);
+        assert_eq!(optimize(vec![repeated.clone()], &[]).len(), 1);
+        assert!(optimize(vec![repeated], &[])[0].is_coalesce());
+    }
+
+    #[test]
+    fn keeps_captured_identifiers() {
+        let states = vec![numeric(1, Some(1)).set_identifier(1), numeric(1, Some(1)).set_identifier(2)];
+        assert_eq!(optimize(states.clone(), &[]).len(), 1);
+        let kept = optimize(states, &[2]);
+        assert_eq!(kept.iter().map(|s| s.get_identifier()).collect::<Vec<usize>>(), vec![1, 2]);
+
+        let wrapped = State::new_block(1, Some(1), vec![numeric(1, Some(1))]).set_identifier(3);
+        let kept = optimize(vec![wrapped], &[3]);
+        assert!(kept[0].is_block_type() && kept[0].get_identifier() == 3);
+    }
+
+    #[test]
+    fn optimizing_keeps_meaning() {
+        // The Builder's hand-built reference tree, a repeating `.N1".".N` followed by letters or a quoted string.
+        let quote = State::new(1, Some(1), vec![PatternType::Specific('"')]);
+        let inner = State::new_coalesce_block(1, Some(1), vec![
+            State::new(1, Some(10), vec![PatternType::Alphabetic]),
+            State::new_block(1, Some(1), vec![quote.clone(), State::new(1, None, vec![PatternType::Everything]), quote]),
+        ]);
+        let core = State::new_block(1, Some(1), vec![
+            numeric(0, Some(10)),
+            State::new(1, Some(1), vec![PatternType::Specific('.')]),
+            numeric(0, Some(10)),
+            State::new_block(1, Some(1), vec![inner]),
+        ]);
+        let hand_built = Pattern::new(vec![State::new_block(1, None, vec![core])]);
+        let optimized = hand_built.clone().optimize(&[]);
+        for input in ["1.2A", ".N", "3.\"x\"1.A", "1.2", "", "1.2\"\"", "12.3AB.4\"a,b\""] {
+            assert_eq!(hand_built.is_match(input).unwrap(), optimized.is_match(input).unwrap(), "on {:?}", input);
+        }
+
+        let inputs = ["", "1", "12", "123", "AB", "ABAB", "A1", "1A", "ab1", "AB12"];
+        let texts = ["1N1N", "1(1N)1(1N)", "1(1\"A\"1N,1\"A\"1\"B\")", "1(1N1A,1N1N).N", "2(1N)1.2(.2N)", "1(1\"AB\",1\"AB\"1N)", ".(1\"AB\")2N"];
+        for text in texts {
+            let original = Builder::init_default(text.to_string()).build().unwrap();
+            let optimized = original.clone().optimize(&[]);
+            for input in inputs {
+                assert_eq!(original.is_match(input).unwrap(), optimized.is_match(input).unwrap(), "{} as {} on {:?}", text, optimized, input);
+            }
+        }
+    }
+}
@@ -0,0 +1,539 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::NO_STATES_ERROR;
+
+use super::alphabet::{Alphabet, ClassSizes};
+use super::automaton::{Dfa, Strings};
+use super::combination::Combination;
+use super::helpers::sequence_length_bounds;
+use super::engine::EngineConfig;
+use super::example::{longest_example, shortest_example};
+use super::matcher::Matcher;
+use super::natural::Natural;
+use super::optimize::optimize;
+use super::program::{Parses, Program};
+use super::rule::{Classification, State};
+use super::table::PatternTable;
+
+/// What came out of checking a string against a Pattern. Negated patterns flip the answer, but we still keep what the
+/// underlying states had to say about the input so callers can show why.
+#[derive(Clone, Debug)]
+pub enum Verdict {
+    /// The states matched the input, with the substring each state took.
+    Matched(Vec<(State, String)>),
+    /// Negated pattern, and the states rejected the input, which is what we wanted.
+    MatchedByRejection,
+    /// The states rejected the input.
+    Rejected,
+    /// Negated pattern, but the states matched. This is the parse that got in the way.
+    RejectedByMatch(Vec<(State, String)>),
+}
+
+impl Verdict {
+    pub fn is_match(&self) -> bool {
+        matches!(self, Self::Matched(_) | Self::MatchedByRejection)
+    }
+
+    /// The substrings each state took, if the states matched at all. Independent of negation.
+    pub fn get_segments(&self) -> Option<&Vec<(State, String)>> {
+        match self {
+            Self::Matched(segments) | Self::RejectedByMatch(segments) => Some(segments),
+            Self::MatchedByRejection | Self::Rejected => None,
+        }
+    }
+}
+
+/// One way the states split up an input: every state that took part, with the substring and character span it took,
+/// plus captures for anything that had an identifier.
+#[derive(Clone, Debug)]
+pub struct Match {
+    segments: Vec<(State, String)>,
+    spans: Vec<(usize, usize)>,
+    captures: BTreeMap<usize, (usize, usize)>,
+}
+
+impl Match {
+    pub fn new(segments: Vec<(State, String)>, spans: Vec<(usize, usize)>, captures: BTreeMap<usize, (usize, usize)>) -> Self {
+        Self {
+            segments,
+            spans,
+            captures,
+        }
+    }
+
+    pub fn get_segments(&self) -> &Vec<(State, String)> {
+        &self.segments
+    }
+
+    /// Start and end of each segment, counted in characters.
+    pub fn get_spans(&self) -> &Vec<(usize, usize)> {
+        &self.spans
+    }
+
+    /// The span the state with this identifier took. If it repeated, that's its last repetition.
+    pub fn get_capture(&self, identifier: usize) -> Option<(usize, usize)> {
+        self.captures.get(&identifier).copied()
+    }
+
+    pub fn into_segments(self) -> Vec<(State, String)> {
+        self.segments
+    }
+}
+
+/// How comparing two patterns came out. When it doesn't hold, there's a string to prove it, as short as they come.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Holds,
+    Counterexample(String),
+}
+
+impl Comparison {
+    pub fn holds(&self) -> bool {
+        matches!(self, Self::Holds)
+    }
+
+    pub fn get_counterexample(&self) -> Option<&str> {
+        match self {
+            Self::Holds => None,
+            Self::Counterexample(text) => Some(text),
+        }
+    }
+}
+
+/// A compiled pattern, usually the output of the Builder. Keeps the states around for inspection, and matches with a
+/// Program compiled from them.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    states: Vec<State>,
+    negated: bool,
+    program: Program,
+    sizes: ClassSizes,
+}
+
+impl Pattern {
+    pub fn new(states: Vec<State>) -> Self {
+        Self {
+            program: Program::compile(&states),
+            states,
+            negated: false,
+            sizes: ClassSizes::default(),
+        }
+    }
+
+    /// Flips the pattern between matching and not matching, like `'?` does in M.
+    pub fn negate(mut self) -> Self {
+        self.negated = !self.negated;
+        self
+    }
+
+    /// Switches how the pattern codes classify characters, which has to line up with how the M server is configured.
+    /// The Builder starts this off from the dialect.
+    pub fn set_classification(mut self, classification: Classification) -> Self {
+        self.states = self.states.into_iter().map(|s| s.set_classification(classification)).collect();
+        self.recompile();
+        self
+    }
+
+    /// Where user-defined codes get their characters from.
+    pub fn set_pattern_table(mut self, table: Arc<PatternTable>) -> Self {
+        self.states = self.states.into_iter().map(|s| s.set_pattern_table(table.clone())).collect();
+        self.recompile();
+        self
+    }
+
+    /// Makes specific characters, literals, ranges and sets ignore case across the whole pattern.
+    pub fn set_case_insensitive(mut self, fold_case: bool) -> Self {
+        self.states = self.states.into_iter().map(|s| s.set_case_insensitive(fold_case)).collect();
+        self.recompile();
+        self
+    }
+
+    /// Tidies the states up without changing what matches. States whose identifiers are in `keep` are left alone.
+    pub fn optimize(mut self, keep: &[usize]) -> Self {
+        self.states = optimize(self.states, keep);
+        self.recompile();
+        self
+    }
+
+    /// Picks which parse wins when there's more than one, which decides the segments and captures that come back.
+    pub fn set_engine_config(mut self, config: EngineConfig) -> Self {
+        self.program = self.program.set_config(config);
+        self
+    }
+
+    /// How big each code class is when counting strings. Plain ASCII unless it's set.
+    pub fn set_class_sizes(mut self, sizes: ClassSizes) -> Self {
+        self.sizes = sizes;
+        self
+    }
+
+    fn recompile(&mut self) {
+        self.program = Program::compile(&self.states).set_config(*self.program.get_config());
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Whether every state, all the way down, classifies characters the ASCII way.
+    pub fn is_ascii_classified(&self) -> bool {
+        fn ascii(state: &State) -> bool {
+            state.get_classification() == Classification::Ascii && state.get_block_states().is_none_or(|states| states.iter().all(ascii))
+        }
+        self.states.iter().all(ascii)
+    }
+
+    // The automata only know the ASCII classes, so anything else could get an answer that's wrong.
+    fn check_ascii(&self) -> Result<(), &'static str> {
+        if self.is_ascii_classified() {
+            Ok(())
+        } else {
+            Err("Only patterns that classify characters the ASCII way can be counted or listed.")
+        }
+    }
+
+    pub fn get_states(&self) -> &Vec<State> {
+        &self.states
+    }
+
+    /// The fewest and most characters a matching input can have, `None` meaning no limit. A negated pattern can match
+    /// anything the states reject, so it has no bounds at all.
+    pub fn length_bounds(&self) -> (usize, Option<usize>) {
+        if self.negated {
+            (0, None)
+        } else {
+            sequence_length_bounds(&self.states)
+        }
+    }
+
+    /// How the states split up the input, if they match it at all. Ignores negation.
+    pub fn parse(&self, input: &str) -> Result<Option<Match>, &'static str> {
+        if self.states.is_empty() {
+            return Err(NO_STATES_ERROR);
+        }
+        Ok(self.program.run(input))
+    }
+
+    /// How many different ways the states can split up the input. Negation isn't taken into account, and
+    /// repetitions past the minimum have to consume something. Saturates at `u128::MAX`.
+    pub fn count_parses(&self, input: &str) -> u128 {
+        self.program.count_runs(input)
+    }
+
+    /// Every parse `count_parses` counts, as Match values, in a fixed order: shorter runs and fewer repetitions first.
+    pub fn all_parses(&self, input: &str) -> Parses<'_> {
+        self.program.all_runs(input)
+    }
+
+    /// How many strings of exactly `len` characters the pattern accepts, negation included, with each class as big as
+    /// the class sizes say. The pattern has to classify characters the ASCII way.
+    pub fn count_strings(&self, len: usize) -> Result<Natural, &'static str> {
+        Ok(self.counts_by_length(len)?.pop().unwrap())
+    }
+
+    /// Same as count_strings, but for every length from 0 up to `max_len` together.
+    pub fn count_strings_up_to(&self, max_len: usize) -> Result<Natural, &'static str> {
+        Ok(self.counts_by_length(max_len)?.iter().fold(Natural::zero(), |total, count| total.add(count)))
+    }
+
+    fn counts_by_length(&self, max_len: usize) -> Result<Vec<Natural>, &'static str> {
+        if self.states.is_empty() {
+            return Err(NO_STATES_ERROR);
+        }
+        self.check_ascii()?;
+        Ok(self.to_automaton(Alphabet::for_states(&self.states, &self.sizes)?).count_by_length(max_len))
+    }
+
+    /// Every string the pattern accepts that's made of the alphabet's characters and no longer than `max_len`, shortest
+    /// first and alphabetical after that. Build the alphabet with Alphabet::from_chars. The pattern has to classify
+    /// characters the ASCII way.
+    pub fn strings_up_to(&self, max_len: usize, alphabet: &Alphabet) -> Result<Strings, &'static str> {
+        if self.states.is_empty() {
+            return Err(NO_STATES_ERROR);
+        }
+        self.check_ascii()?;
+        Ok(self.automaton(&self.states, alphabet.clone()).strings(max_len))
+    }
+
+    /// A shortest string the pattern accepts, made of readable characters like 'A' and '0'.
+    pub fn shortest_example(&self) -> Option<String> {
+        if !self.negated {
+            return shortest_example(&self.states);
+        }
+        self.to_automaton(Alphabet::for_states(&self.states, &ClassSizes::default()).ok()?).shortest()
+    }
+
+    /// The longest string the pattern accepts, or None when there's no end to how long they get. Negated patterns
+    /// always go on forever.
+    pub fn longest_example(&self) -> Option<String> {
+        if self.negated {
+            return None;
+        }
+        longest_example(&self.states)
+    }
+
+    /// Whether every string this pattern accepts, the other one does too. If not, the counterexample is a string only
+    /// this one accepts. Both patterns have to classify characters the ASCII way.
+    pub fn is_subset_of(&self, other: &Pattern) -> Result<Comparison, &'static str> {
+        self.compare(other, |this, other| this && !other)
+    }
+
+    /// Whether both patterns accept exactly the same strings. If not, the counterexample is accepted by one and not
+    /// the other. Both patterns have to classify characters the ASCII way.
+    pub fn is_equivalent(&self, other: &Pattern) -> Result<Comparison, &'static str> {
+        self.compare(other, |this, other| this != other)
+    }
+
+    /// Looks for the shortest string where `differs` is true, given whether each pattern accepts it.
+    fn compare(&self, other: &Pattern, differs: fn(bool, bool) -> bool) -> Result<Comparison, &'static str> {
+        if self.states.is_empty() || other.states.is_empty() {
+            return Err(NO_STATES_ERROR);
+        }
+        // The automata only know the ASCII classes, so anything else could get an answer that's wrong.
+        if !self.is_ascii_classified() || !other.is_ascii_classified() {
+            return Err("Only patterns that classify characters the ASCII way can be compared.");
+        }
+
+        let both: Vec<State> = self.states.iter().chain(&other.states).cloned().collect();
+        let alphabet = Alphabet::separating(&both);
+        let difference = self.to_automaton(alphabet.clone()).product(&other.to_automaton(alphabet), differs);
+        Ok(difference.shortest().map_or(Comparison::Holds, Comparison::Counterexample))
+    }
+
+    /// Matches what this and the other both match. See Combination.
+    pub fn intersection<C: Into<Combination>>(&self, other: C) -> Combination {
+        Combination::from(self).intersection(other)
+    }
+
+    /// Matches what either this or the other matches.
+    pub fn union<C: Into<Combination>>(&self, other: C) -> Combination {
+        Combination::from(self).union(other)
+    }
+
+    /// Matches whatever this doesn't, as a Combination. Unlike negate, this can go on to be combined with others.
+    pub fn complement(&self) -> Combination {
+        Combination::from(self).complement()
+    }
+
+    /// The pattern as an automaton over the alphabet, read in ASCII mode, negation included.
+    pub fn to_automaton(&self, alphabet: Alphabet) -> Dfa {
+        let states: Vec<State> = self.states.iter().map(|s| s.clone().set_classification(Classification::Ascii)).collect();
+        self.automaton(&states, alphabet)
+    }
+
+    fn automaton(&self, states: &[State], alphabet: Alphabet) -> Dfa {
+        let dfa = Dfa::compile(states, alphabet);
+        if self.negated { dfa.complement() } else { dfa }
+    }
+
+    /// Runs the states over the input and reports the outcome, with negation already accounted for.
+    pub fn evaluate(&self, input: &str) -> Result<Verdict, &'static str> {
+        let segments = self.parse(input)?.map(Match::into_segments);
+
+        Ok(match (segments, self.negated) {
+            (Some(segments), false) => Verdict::Matched(segments),
+            (Some(segments), true) => Verdict::RejectedByMatch(segments),
+            (None, false) => Verdict::Rejected,
+            (None, true) => Verdict::MatchedByRejection,
+        })
+    }
+
+    /// Same answer as evaluate, but without working out how the states split up the input.
+    pub fn is_match(&self, input: &str) -> Result<bool, &'static str> {
+        if self.states.is_empty() {
+            return Err(NO_STATES_ERROR);
+        }
+        Ok(self.program.is_match(input) != self.negated)
+    }
+
+    /// Counts how many of the inputs the pattern accepts. For a negated pattern, that's the ones the states reject.
+    pub fn count_matches<'a, I>(&self, inputs: I) -> Result<usize, &'static str>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        Matcher::count_matches(self, inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::builder::Builder;
+    use crate::regex::testing::compile;
+
+    #[test]
+    fn compiled_patterns_match() {
+        let pattern = compile("3N1\"-\"2N1\"-\"4N");
+        assert!(pattern.is_match("123-45-6789").unwrap());
+        assert!(!pattern.is_match("123-456-789").unwrap());
+
+        let pattern = compile("1U.AN1(1\"-\",1\".\").E");
+        assert!(pattern.is_match("Ab1-").unwrap());
+        assert!(pattern.is_match("X.anything at all").unwrap());
+        assert!(!pattern.is_match("x-").unwrap());
+
+        assert!(compile(".N").is_match("").unwrap());
+        assert!(!compile("1.N").is_match("").unwrap());
+        assert!(compile("2(1A,1N)").is_match("A1").unwrap());
+    }
+
+    #[test]
+    fn negated_patterns_invert() {
+        let pattern = compile("'1N");
+        assert!(pattern.is_negated());
+        assert!(pattern.is_match("A").unwrap());
+        assert!(!pattern.is_match("7").unwrap());
+
+        match pattern.evaluate("7").unwrap() {
+            Verdict::RejectedByMatch(segments) => assert_eq!(segments[0].1, "7"),
+            other => panic!("unexpected verdict {:?}", other),
+        }
+        assert!(pattern.evaluate("A").unwrap().get_segments().is_none());
+
+        let lines = ["1", "22", "A", ""];
+        assert_eq!(compile(".N").count_matches(lines).unwrap(), 3);
+        assert_eq!(compile("'.N").count_matches(lines).unwrap(), 1);
+        assert!(!compile("'.N").negate().is_negated());
+    }
+
+    #[test]
+    fn classification_modes() {
+        let alpha = compile("1A");
+        let ascii = alpha.clone().set_classification(Classification::Ascii);
+        let latin1 = alpha.clone().set_classification(Classification::Latin1);
+        let unicode = alpha.set_classification(Classification::Unicode);
+
+        assert!(ascii.is_match("e").unwrap() && latin1.is_match("e").unwrap() && unicode.is_match("e").unwrap());
+        assert!(!ascii.is_match("\u{e9}").unwrap());
+        assert!(latin1.is_match("\u{e9}").unwrap());
+        assert!(unicode.is_match("\u{e9}").unwrap());
+        assert!(!latin1.is_match("\u{416}").unwrap());
+        assert!(unicode.is_match("\u{416}").unwrap());
+
+        // The multiplication sign sits between the accented capitals, but it's punctuation.
+        let upper = compile("1U").set_classification(Classification::Latin1);
+        assert!(upper.is_match("\u{c9}").unwrap());
+        assert!(!upper.is_match("\u{d7}").unwrap());
+        assert!(compile("1P").set_classification(Classification::Latin1).is_match("\u{d7}").unwrap());
+        assert!(!compile("1P").set_classification(Classification::Ascii).is_match("\u{d7}").unwrap());
+        assert!(compile("1C").set_classification(Classification::Latin1).is_match("\u{85}").unwrap());
+
+        // Counting still works in characters when the input isn't ASCII.
+        let name = compile("1U.L").set_classification(Classification::Latin1);
+        assert!(name.is_match("\u{c9}mile").unwrap());
+        match name.evaluate("\u{c9}mile").unwrap() {
+            Verdict::Matched(segments) => assert_eq!(segments[0].1, "\u{c9}"),
+            other => panic!("unexpected verdict {:?}", other),
+        }
+        assert!(compile("1.E").is_match("\u{416}\u{e9}").unwrap());
+    }
+
+    #[test]
+    fn bad_pattern_text() {
+        assert!(Builder::init_default("1".to_string()).build().is_err());
+        assert!(Builder::init_default("N".to_string()).build().is_err());
+        assert!(Builder::init_default("1\"abc".to_string()).build().is_err());
+        assert!(Builder::init_default("1(1N".to_string()).build().is_err());
+        assert!(Builder::init_default("3.2N".to_string()).build().is_err());
+        assert!(Builder::init_default("1Q".to_string()).build().is_err());
+    }
+
+    #[test]
+    fn matches_and_captures() {
+        use crate::regex::engine::Disambiguation;
+
+        // Identifiers go to atoms in the order they appear, so the two numbers are 1 and 3.
+        let pattern = compile(".N1\"-\".N");
+        let found = pattern.parse("12-345").unwrap().unwrap();
+        assert_eq!(found.get_capture(1), Some((0, 2)));
+        assert_eq!(found.get_capture(3), Some((3, 6)));
+        assert_eq!(found.get_spans(), &vec![(0, 2), (2, 3), (3, 6)]);
+        assert!(pattern.parse("12-x").unwrap().is_none());
+
+        let lazy = compile(".E1(1\"-\",1\"--\").E");
+        let longest = lazy.clone().set_engine_config(EngineConfig::new().set_disambiguation(Disambiguation::LeftmostLongest));
+        assert_eq!(lazy.parse("a--b").unwrap().unwrap().get_capture(1), Some((0, 1)));
+        assert_eq!(longest.parse("a--b").unwrap().unwrap().get_capture(1), Some((0, 2)));
+        assert_eq!(longest.parse("a--b").unwrap().unwrap().get_capture(2), Some((2, 3)));
+
+        // The config sticks around when the pattern gets changed afterwards.
+        let recompiled = longest.set_case_insensitive(true);
+        assert_eq!(recompiled.parse("a--b").unwrap().unwrap().get_capture(1), Some((0, 2)));
+    }
+
+    #[test]
+    fn counts_strings() {
+        assert_eq!(compile("2U4N").count_strings(6).unwrap().to_u128(), Some(26 * 26 * 10_000));
+        assert_eq!(compile("2U4N").count_strings(5).unwrap(), Natural::zero());
+        let sizes = ClassSizes::new().set_upper(24).set_alphabetic(50).set_everything(126);
+        assert_eq!(compile("2U4N").set_class_sizes(sizes).count_strings(6).unwrap().to_u128(), Some(24 * 24 * 10_000));
+        assert_eq!(compile("1.3N").count_strings_up_to(10).unwrap().to_u128(), Some(1110));
+        assert_eq!(compile("1N").negate().count_strings(1).unwrap().to_u128(), Some(118));
+        assert_eq!(compile(".E").count_strings(30).unwrap().to_string(), "1645504557321206042154969182557350504982735865633579863348609024");
+        assert!(compile("1\"0123456789\"").set_class_sizes(ClassSizes::new().set_numeric(5)).count_strings(10).is_err());
+    }
+
+    #[test]
+    fn lists_strings() {
+        let alphabet = Alphabet::from_chars("YN01".chars());
+        let listed: Vec<String> = compile("1(1\"Y\",1\"N\")1.2N").strings_up_to(3, &alphabet).unwrap().collect();
+        assert_eq!(listed[..5], ["N0", "N1", "Y0", "Y1", "N00"]);
+        assert_eq!(listed.len(), 4 + 8);
+        let negated: Vec<String> = compile(".E").negate().strings_up_to(3, &alphabet).unwrap().collect();
+        assert!(negated.is_empty());
+    }
+
+    #[test]
+    fn only_ascii_patterns_count() {
+        // Under Unicode, 'Ω' is upper case, which the automaton's ASCII classes know nothing about.
+        let unicode = compile("1U").set_classification(Classification::Unicode);
+        assert!(unicode.count_strings(1).is_err());
+        assert!(unicode.count_strings_up_to(1).is_err());
+        assert!(unicode.strings_up_to(1, &Alphabet::from_chars("AΩ".chars())).is_err());
+        let ascii: Vec<String> = compile("1U").strings_up_to(1, &Alphabet::from_chars("AΩ".chars())).unwrap().collect();
+        assert_eq!(ascii, ["A"]);
+    }
+
+    #[test]
+    fn examples() {
+        assert_eq!(compile("3N1\"-\"2N1\"-\"4N").shortest_example().as_deref(), Some("000-00-0000"));
+        assert_eq!(compile("1.3A").longest_example().as_deref(), Some("AAA"));
+        assert_eq!(compile(".A").longest_example(), None);
+        assert_eq!(compile("1.3A").negate().shortest_example().as_deref(), Some(""));
+        assert_eq!(compile(".N").negate().shortest_example().as_deref(), Some("A"));
+        assert_eq!(compile(".A").negate().shortest_example().as_deref(), Some("0"));
+        assert_eq!(compile(".E").negate().shortest_example(), None);
+    }
+
+    #[test]
+    fn compares_patterns() {
+        assert!(compile("1.3N").is_equivalent(&compile(".2N1N")).unwrap().holds());
+        assert!(compile("1(1\"Y\",1\"N\")").is_equivalent(&compile("1(1\"N\",1\"Y\")")).unwrap().holds());
+        assert!(compile("3N").is_subset_of(&compile("1.5N")).unwrap().holds());
+
+        let found = compile("1.5N").is_subset_of(&compile("3N")).unwrap();
+        assert_eq!(found.get_counterexample(), Some("0"));
+        let found = compile("1.3N").is_equivalent(&compile("0.3N")).unwrap();
+        assert_eq!(found.get_counterexample(), Some(""));
+        let found = compile("2A").is_equivalent(&compile("1U1A")).unwrap();
+        assert_eq!(found.get_counterexample(), Some("aA"));
+
+        // Whatever comes back really does tell them apart.
+        let pairs = [("1.E", "1E.E"), (".A1\"-\"", "1.A1P"), ("2(1A,1N)", "1AN1AN"), ("1\"a\".E", "1L.E")];
+        for (this, that) in pairs {
+            let (this, that) = (compile(this), compile(that));
+            match this.is_equivalent(&that).unwrap() {
+                Comparison::Holds => {}
+                Comparison::Counterexample(text) => assert_ne!(this.is_match(&text).unwrap(), that.is_match(&text).unwrap()),
+            }
+        }
+        assert!(compile("1A").negate().is_equivalent(&compile("1A").negate().negate().negate()).unwrap().holds());
+
+        // Under Unicode, 1A takes letters with no case that 1(1U,1L) doesn't, and the automata can't tell.
+        let letter = compile("1A").set_classification(Classification::Unicode);
+        assert!(letter.is_match("\u{4e2d}").unwrap() && !compile("1(1U,1L)").set_classification(Classification::Unicode).is_match("\u{4e2d}").unwrap());
+        assert!(letter.is_equivalent(&compile("1(1U,1L)")).is_err());
+        assert!(compile("1(1U,1L)").is_subset_of(&letter).is_err());
+    }
+}
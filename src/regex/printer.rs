@@ -0,0 +1,311 @@
+use std::fmt;
+
+use super::helpers::{case_variants, normalize_ranges};
+use super::pattern::Pattern;
+use super::rule::{PatternType, State};
+
+// Renders states back into M pattern text. Trees shaped like the Builder's output print as plain M. Anything M can't
+// say directly (ranges, sets, negated codes) uses the Builder's extended syntax, and predicates show up as `{name}`,
+// which nothing parses.
+
+/// `n`, `n.m`, `n.`, `.m` or `.`, whichever is shortest for the bounds.
+pub fn repeat_count(min: usize, max: Option<usize>) -> String {
+    match (min, max) {
+        (min, Some(max)) if min == max => min.to_string(),
+        (0, None) => ".".to_string(),
+        (0, Some(max)) => format!(".{}", max),
+        (min, None) => format!("{}.", min),
+        (min, Some(max)) => format!("{}.{}", min, max),
+    }
+}
+
+/// Wraps text in quotes, doubling any quotes inside.
+pub fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+/// The text for a pattern type that can sit in a code group next to other codes, if it can.
+fn code_text(pattern: &PatternType) -> Option<String> {
+    let text = match pattern {
+        PatternType::Alphabetic => "A".to_string(),
+        PatternType::Control => "C".to_string(),
+        PatternType::Everything => "E".to_string(),
+        PatternType::Lowercase => "L".to_string(),
+        PatternType::Numeric => "N".to_string(),
+        PatternType::Punctuation => "P".to_string(),
+        PatternType::Uppercase => "U".to_string(),
+        PatternType::Custom(code) => code.to_string(),
+        PatternType::Range(low, high) => bracket(&[(*low, *high)], false),
+        PatternType::Set(ranges) => bracket(ranges, false),
+        PatternType::Not(inner) => match inner.as_ref() {
+            PatternType::Range(low, high) => bracket(&[(*low, *high)], true),
+            PatternType::Set(ranges) => bracket(ranges, true),
+            _ => return None,
+        },
+        PatternType::AnyOf(patterns) => patterns.iter().map(code_text).collect::<Option<String>>()?,
+        PatternType::Predicate(predicate) => format!("{{{}}}", predicate.get_name()),
+        PatternType::Specific(_) | PatternType::Literal(_) => return None,
+    };
+    Some(text)
+}
+
+fn bracket(ranges: &[(char, char)], negated: bool) -> String {
+    let escape = |c: char| if matches!(c, ']' | '\\' | '-' | '^') { format!("\\{}", c) } else { c.to_string() };
+    let mut output = String::from(if negated { "[^" } else { "[" });
+    for (low, high) in ranges {
+        output.push_str(&escape(*low));
+        if high != low {
+            output.push('-');
+            output.push_str(&escape(*high));
+        }
+    }
+    output.push(']');
+    output
+}
+
+/// Everything after the repeat count for a single character pattern type.
+fn single_text(pattern: &PatternType, fold_case: bool) -> String {
+    let suffix = if fold_case { "i" } else { "" };
+    match pattern {
+        PatternType::Specific(c) => format!("{}{}", quote(&c.to_string()), suffix),
+        PatternType::Literal(text) => format!("{}{}", quote(text), suffix),
+        PatternType::Not(inner) => match (code_text(pattern), inner.as_ref()) {
+            (Some(codes), _) => codes,
+            (None, PatternType::Specific(c)) => bracket(&character_ranges(*c, fold_case), true),
+            (None, PatternType::Literal(text)) if !text.is_empty() => bracket(&character_ranges(text.chars().next().unwrap(), fold_case), true),
+            // Whatever matched nothing, so the negation matches anything.
+            _ => match negated_codes(inner, fold_case) {
+                codes if codes.is_empty() => "E".to_string(),
+                codes => format!("'{}", codes),
+            },
+        },
+        _ => code_text(pattern).unwrap_or_default(),
+    }
+}
+
+/// A character as bracket ranges, with every case when the state ignores it.
+fn character_ranges(c: char, fold_case: bool) -> Vec<(char, char)> {
+    let variants = if fold_case { case_variants(c) } else { vec![c] };
+    normalize_ranges(variants.into_iter().map(|v| (v, v)).collect())
+}
+
+/// What goes after the `'` for a negated pattern type. Characters can't go in a code group as quotes, so they go in as
+/// brackets.
+fn negated_codes(pattern: &PatternType, fold_case: bool) -> String {
+    match pattern {
+        PatternType::Specific(c) => bracket(&character_ranges(*c, fold_case), false),
+        // A literal as a single character pattern type only ever looks at its first character.
+        PatternType::Literal(text) => text.chars().next().map(|c| bracket(&character_ranges(c, fold_case), false)).unwrap_or_default(),
+        PatternType::AnyOf(patterns) => patterns.iter().map(|p| negated_codes(p, fold_case)).collect(),
+        _ => code_text(pattern).unwrap_or_default(),
+    }
+}
+
+fn write_plain(state: &State, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let count = repeat_count(state.get_min(), state.get_max());
+    let patterns = state.get_patterns();
+    let fold_case = state.is_case_insensitive();
+
+    if let Some(codes) = patterns.iter().map(code_text).collect::<Option<String>>().filter(|c| !c.is_empty()) {
+        return write!(f, "{}{}", count, codes);
+    }
+
+    match patterns.as_slice() {
+        [] => write!(f, "{}[]", count),
+        [only] => write!(f, "{}{}", count, single_text(only, fold_case)),
+        // Mixed codes and characters can't share a code group, but every repetition picking one of them is the same.
+        _ => {
+            let options: Vec<String> = patterns.iter().map(|p| format!("1{}", single_text(p, fold_case))).collect();
+            write!(f, "{}({})", count, options.join(","))
+        }
+    }
+}
+
+/// An alternative inside a group. Sequences that the Builder wrapped in a block print without the wrapper.
+fn option_text(state: &State) -> String {
+    match state.get_block_states() {
+        Some(states) if !state.is_coalesce() && state.get_min() == 1 && state.get_max() == Some(1) && !states.is_empty() => {
+            sequence_text(states)
+        }
+        _ => state.to_string(),
+    }
+}
+
+fn sequence_text(states: &[State]) -> String {
+    states.iter().map(|s| s.to_string()).collect()
+}
+
+fn write_block(state: &State, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let count = repeat_count(state.get_min(), state.get_max());
+    let states = state.get_block_states().unwrap();
+
+    if state.is_coalesce() {
+        // A coalesce picks one option and repeats that one, so the count goes on each option.
+        let options: Vec<String> = if state.get_min() == 1 && state.get_max() == Some(1) {
+            states.iter().map(option_text).collect()
+        } else {
+            states.iter().map(|s| format!("{}({})", count, option_text(s))).collect()
+        };
+        return write!(f, "1({})", options.join(","));
+    }
+
+    // The Builder's group shape, where every repetition picks its own option.
+    if let Some(options) = state.get_group_options() {
+        let options: Vec<String> = options.iter().map(option_text).collect();
+        return write!(f, "{}({})", count, options.join(","));
+    }
+    match states.as_slice() {
+        [] => write!(f, "{}\"\"", count),
+        _ => write!(f, "{}({})", count, sequence_text(states)),
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_block_type() {
+            write_block(self, f)
+        } else {
+            write_plain(self, f)
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_negated() {
+            write!(f, "'")?;
+        }
+        write!(f, "{}", sequence_text(self.get_states()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::builder::Builder;
+
+    fn extended(text: &str) -> Pattern {
+        Builder::init_default(text.to_string()).set_extended_syntax(true).build().unwrap_or_else(|e| panic!("{}: {}", text, e))
+    }
+
+    #[test]
+    fn prints_m_syntax() {
+        let cases = [
+            ("3N1\"-\"2N", "3N1\"-\"2N"),
+            ("1.1N0.AP2.U.3L", "1N.AP2.U.3L"),
+            ("1\"say \"\"hi\"\"\"", "1\"say \"\"hi\"\"\""),
+            ("2(1\"Y\",1\"N\")1.2N", "2(1\"Y\",1\"N\")1.2N"),
+            ("'1(2A1N,.E)", "'1(2A1N,.E)"),
+            ("1[a-f0-9_]1[^\\]]1'NP1\"ok\"i", "1[0-9_a-f]1[^\\]]1'NP1\"ok\"i"),
+        ];
+        for (text, printed) in cases {
+            assert_eq!(extended(text).to_string(), printed);
+        }
+    }
+
+    #[test]
+    fn prints_hand_built_trees() {
+        let coalesce = State::new_coalesce_block(1, Some(4), vec![
+            State::new(1, Some(1), vec![PatternType::Alphabetic]),
+            State::new(1, Some(1), vec![PatternType::Numeric]),
+        ]);
+        assert_eq!(coalesce.to_string(), "1(1.4(1A),1.4(1N))");
+
+        let mixed = State::new(2, None, vec![PatternType::Numeric, PatternType::Specific('.')]);
+        assert_eq!(mixed.to_string(), "2.(1N,1\".\")");
+
+        let block = State::new_block(0, Some(2), vec![mixed, State::new_literal(1, Some(1), "x")]);
+        assert_eq!(block.to_string(), ".2(2.(1N,1\".\")1\"x\")");
+
+        let predicate = State::new(1, Some(1), vec![PatternType::predicate("vowel", |c| "aeiou".contains(c))]);
+        assert_eq!(predicate.to_string(), "1{vowel}");
+
+        let not_quote = State::new(1, Some(1), vec![PatternType::Not(Box::new(PatternType::Specific('"')))]);
+        assert_eq!(not_quote.to_string(), "1[^\"]");
+        assert_eq!(not_quote.set_case_insensitive(true).to_string(), "1[^\"]");
+        let not_a = State::new(1, Some(1), vec![PatternType::Not(Box::new(PatternType::Specific('a')))]);
+        assert_eq!(not_a.set_case_insensitive(true).to_string(), "1[^Aa]");
+
+        // Hand built trees print as something that matches the same strings.
+        let printed = Pattern::new(vec![coalesce]).to_string();
+        let reparsed = extended(&printed);
+        assert!(reparsed.is_match("ABC").unwrap());
+        assert!(reparsed.is_match("12").unwrap());
+        assert!(!reparsed.is_match("A1").unwrap());
+    }
+
+    /// Tiny xorshift so the property tests are repeatable without pulling in a crate.
+    struct TestRng(u64);
+
+    impl TestRng {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, options: &[&'a str]) -> &'a str {
+            options[self.below(options.len())]
+        }
+    }
+
+    fn random_count(rng: &mut TestRng) -> String {
+        let min = rng.below(3);
+        match rng.below(4) {
+            0 => min.to_string(),
+            1 => format!("{}.", if min == 0 { String::new() } else { min.to_string() }),
+            2 => format!(".{}", min + 1),
+            _ => format!("{}.{}", min, min + rng.below(3)),
+        }
+    }
+
+    fn random_sequence(rng: &mut TestRng, depth: usize) -> String {
+        let mut text = String::new();
+        for _ in 0..=rng.below(3) {
+            text.push_str(&random_count(rng));
+            match rng.below(if depth > 0 { 4 } else { 3 }) {
+                0 => text.push_str(rng.pick(&["A", "N", "U", "L", "P", "E", "AN", "UP", "'N", "[a-c]", "[^a]"])),
+                1 => text.push_str(rng.pick(&["\"a\"", "\"ab\"", "\"\"\"\"", "\"-1\"", "\"\"", "\"B\"i"])),
+                2 => text.push_str(rng.pick(&["C", "[a\\-]", "\"aB\"i", "'[0-9]"])),
+                _ => {
+                    let options: Vec<String> = (0..=rng.below(2)).map(|_| random_sequence(rng, depth - 1)).collect();
+                    text.push_str(&format!("({})", options.join(",")));
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn round_trip_property() {
+        let mut rng = TestRng(0x2545_f491_4f6c_dd1d);
+        let inputs = ["", "a", "1", "ab", "a1", "\"", "-1", "AB", "aB1", "ab-", "1a1a", "abab"];
+        // The parser never makes these, so they get added by hand.
+        let negated = [
+            PatternType::Specific('a'),
+            PatternType::Specific(']'),
+            PatternType::Literal("ab".to_string()),
+            PatternType::Literal(String::new()),
+            PatternType::AnyOf(vec![PatternType::Numeric, PatternType::Specific('-')]),
+        ];
+        for _ in 0..300 {
+            let text = random_sequence(&mut rng, 2);
+            let mut states = extended(&text).get_states().clone();
+            if rng.below(2) == 0 {
+                let inner = negated[rng.below(negated.len())].clone();
+                let state = State::new(rng.below(2), Some(2), vec![PatternType::Not(Box::new(inner))]);
+                states.push(state.set_case_insensitive(rng.below(2) == 0));
+            }
+            let original = Pattern::new(states);
+            let printed = original.to_string();
+            let reparsed = extended(&printed);
+
+            // Printing is a fixed point after the first pass, and the meaning never changes.
+            assert_eq!(reparsed.to_string(), printed, "from {}", text);
+            for input in inputs {
+                assert_eq!(original.is_match(input).unwrap(), reparsed.is_match(input).unwrap(), "{} vs {} on {:?}", text, printed, input);
+            }
+        }
+    }
+}
@@ -0,0 +1,1124 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use super::engine::{compare_leftmost_longest, Disambiguation, EngineConfig};
+use super::helpers::sequence_length_bounds;
+use super::pattern::Match;
+use super::rule::State;
+
+// A compiled form of a state tree. Every state becomes one node in a flat arena and refers to what's inside it by
+// index, so nothing gets copied once it's compiled. Repetition is a loop counter carried along while matching, rather
+// than the Engine's approach of splicing `multiplicity` copies of a block into the state vector, so `1.1000N` costs
+// the same to set up as `1N`.
+//
+// Matching backtracks the same way the Engine does, including which parse wins under each EngineConfig: lazily, plain
+// states try their shortest run first and blocks their fewest repetitions, greedily the other way round, and coalesce
+// blocks try their options in order. Past the minimum, a repetition has to consume something, since an empty one could
+// always be left out, which is what keeps unbounded repetition finite.
+//
+// What's left to do and what's left to try are kept in vectors rather than on the call stack, so a repetition that runs
+// a hundred thousand times is just a longer vector and not a stack overflow.
+
+/// Index of a node in a Program.
+pub type NodeId = usize;
+
+#[derive(Clone, Debug)]
+enum Kind {
+    /// A plain or literal state, which is where characters actually get consumed.
+    Leaf(State),
+    /// A block, repeating the whole sequence each time.
+    Sequence(Vec<NodeId>),
+    /// A coalesce block, which picks one option and repeats that.
+    Choice(Vec<NodeId>),
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    identifier: usize,
+    min: usize,
+    max: Option<usize>,
+    kind: Kind,
+    // Leaves that could take the first character of a repetition, and whether the node can come up empty.
+    first: Vec<NodeId>,
+    can_be_empty: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    nodes: Vec<Node>,
+    root: Vec<NodeId>,
+    length_bounds: (usize, Option<usize>),
+    config: EngineConfig,
+}
+
+/// Where a match is up to. The trace has every leaf that consumed something (or nothing), with the span it took, and
+/// the groups have the spans of blocks that carry an identifier. The frames and choices are what's left to do and
+/// what's left to try, kept here instead of on the call stack so long inputs don't need deep recursion.
+struct Run<'a> {
+    input: &'a [char],
+    trace: Trace,
+    groups: Trace,
+    frames: Vec<(Goal, Continuation)>,
+    choices: Vec<Choice>,
+    // Only kept when going for the leftmost-longest parse.
+    outline: Option<Outline>,
+    // Only kept when stopping at the first parse.
+    memo: Option<Memo>,
+}
+
+/// Visits along with their spans, as `(node, start, end)`.
+type Trace = Vec<(NodeId, usize, usize)>;
+
+/// The goals still to do, as the frame holding the first of them, each frame pointing on to the next. `None` means
+/// there's nothing left, so the match is done if the input is.
+type Continuation = Option<usize>;
+
+/// Something a match still has to get through.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+enum Goal {
+    /// The children of a block, or the top level if there's no block, from `from` onwards.
+    Sequence(Option<NodeId>, usize),
+    Node(NodeId),
+    /// Repetitions of a block's body, `done` of them so far. Coalesce blocks have the option they picked.
+    Repeat(NodeId, Option<usize>, usize),
+    /// A repetition that started at the position given is over, so it's on to the next one.
+    Repeated(NodeId, Option<usize>, usize, usize),
+    /// A block that started at the position given is over, so its span gets recorded if it has an identifier, and its
+    /// entry in the outline, if there's one, gets its end.
+    Group(NodeId, usize, Option<usize>),
+}
+
+/// What's left to try at a choice point.
+#[derive(Clone, Debug)]
+enum Alternative {
+    /// The other runs of a leaf, counted in units of `step`, taken from the back when going greedily.
+    Lengths(NodeId, usize, RangeInclusive<usize>),
+    /// The options of a coalesce block from this one on.
+    Options(NodeId, usize),
+    /// Another repetition of a block, when the fewest repetitions got tried first.
+    Another(NodeId, Option<usize>, usize),
+    /// Carrying on without another repetition, when the most got tried first.
+    Stop,
+}
+
+/// A point to backtrack to, with how long everything was when it was made so whatever came after can be thrown away.
+#[derive(Clone, Debug)]
+struct Choice {
+    alternative: Alternative,
+    pos: usize,
+    k: Continuation,
+    trace: usize,
+    groups: usize,
+    frames: usize,
+    outline: (usize, usize),
+}
+
+/// Where every block and leaf visited so far ended, in the order they started, a block before what's inside it. That's
+/// what leftmost-longest compares parses by. Blocks still going have `usize::MAX`.
+#[derive(Default)]
+struct Outline {
+    ends: Vec<usize>,
+    // The blocks that got their end since the start, so backtracking can open them back up.
+    closed: Vec<usize>,
+    best: Option<Vec<usize>>,
+    // How many of the ends so far are settled and the same as the best parse's.
+    agree: usize,
+}
+
+impl Outline {
+    fn open(&mut self) -> usize {
+        self.ends.push(usize::MAX);
+        self.ends.len() - 1
+    }
+
+    fn close(&mut self, entry: usize, end: usize) {
+        self.ends[entry] = end;
+        self.closed.push(entry);
+    }
+
+    fn rewind(&mut self, (ends, closed): (usize, usize)) {
+        for entry in self.closed.drain(closed..) {
+            self.ends[entry] = usize::MAX;
+            self.agree = self.agree.min(entry);
+        }
+        self.ends.truncate(ends);
+        self.agree = self.agree.min(ends);
+    }
+
+    /// Whether the parse so far could still beat the best one, which it can't once the first end that differs is
+    /// settled and shorter.
+    fn promising(&mut self) -> bool {
+        let Some(best) = &self.best else {
+            return true;
+        };
+        while let (Some(end), Some(best)) = (self.ends.get(self.agree), best.get(self.agree)) {
+            match end.cmp(best) {
+                _ if *end == usize::MAX => return true,
+                Ordering::Less => return false,
+                Ordering::Greater => return true,
+                Ordering::Equal => self.agree += 1,
+            }
+        }
+        true
+    }
+
+    /// Keeps the parse that just finished if it beats the best one so far.
+    fn finish(&mut self) -> bool {
+        let better = self.best.as_ref().is_none_or(|best| compare_leftmost_longest(&self.ends, best).is_gt());
+        if better {
+            self.best = Some(self.ends.clone());
+            self.agree = self.ends.len();
+        }
+        better
+    }
+}
+
+/// Where a run that stops at the first parse has already been, checked whenever it's about to take a leaf or it's got
+/// nothing left to do. What's left to do gets a number by what's in it rather than which frames it's kept in, so the
+/// same work at the same position always comes out as the same pair. Getting back to a pair means it went nowhere the
+/// first time, or the search would have stopped there, so it isn't worth going through again. That's what keeps
+/// something like `.(.N)1"x"` from trying every way of cutting up the digits.
+struct Memo {
+    numbers: HashMap<(Goal, Option<usize>), usize>,
+    // The number of what's left to do from each frame on, alongside the frames. Only worked out once it's needed.
+    frames: Vec<Option<usize>>,
+    seen: HashSet<(usize, Option<usize>)>,
+    // For blocks with no most repetitions, the count past which one more doesn't change anything.
+    floors: Vec<Option<usize>>,
+}
+
+impl Memo {
+    fn new(program: &Program) -> Self {
+        let floors = program.nodes.iter().map(|node| node.max.is_none().then_some(node.min)).collect();
+        Self { numbers: HashMap::new(), frames: Vec::new(), seen: HashSet::new(), floors }
+    }
+
+    /// The goal with whatever doesn't change how the match carries on taken out.
+    fn key(&self, goal: Goal) -> Goal {
+        let done = |id: NodeId, done: usize| self.floors[id].map_or(done, |floor| done.min(floor));
+        match goal {
+            Goal::Repeat(id, option, count) => Goal::Repeat(id, option, done(id, count)),
+            Goal::Repeated(id, option, count, start) => Goal::Repeated(id, option, done(id, count), start),
+            Goal::Group(id, _, _) => Goal::Group(id, 0, None),
+            other => other,
+        }
+    }
+
+    /// Numbers the frames from `k` on, going as far down as it has to.
+    fn number(&mut self, frames: &[(Goal, Continuation)], k: Continuation) -> Option<usize> {
+        let mut unnumbered = Vec::new();
+        let mut at = k;
+        while let Some(frame) = at.filter(|frame| self.frames[*frame].is_none()) {
+            unnumbered.push(frame);
+            at = frames[frame].1;
+        }
+        for frame in unnumbered.into_iter().rev() {
+            let (goal, next) = frames[frame];
+            let key = (self.key(goal), next.and_then(|next| self.frames[next]));
+            let fresh = self.numbers.len();
+            self.frames[frame] = Some(*self.numbers.entry(key).or_insert(fresh));
+        }
+        k.and_then(|frame| self.frames[frame])
+    }
+
+    /// Whether the match hasn't been at this position with this much left to do before, remembering it if not.
+    fn visit(&mut self, frames: &[(Goal, Continuation)], pos: usize, k: Continuation) -> bool {
+        let number = self.number(frames, k);
+        self.seen.insert((pos, number))
+    }
+}
+
+impl<'a> Run<'a> {
+    fn new(input: &'a [char]) -> Self {
+        Self { input, trace: Vec::new(), groups: Vec::new(), frames: Vec::new(), choices: Vec::new(), outline: None, memo: None }
+    }
+
+    fn push(&mut self, goal: Goal, k: Continuation) -> Continuation {
+        if let Some(memo) = self.memo.as_mut() {
+            memo.frames.push(None);
+        }
+        self.frames.push((goal, k));
+        Some(self.frames.len() - 1)
+    }
+
+    fn choose(&mut self, alternative: Alternative, pos: usize, k: Continuation) {
+        let (trace, groups, frames) = (self.trace.len(), self.groups.len(), self.frames.len());
+        let outline = self.outline.as_ref().map_or((0, 0), |o| (o.ends.len(), o.closed.len()));
+        self.choices.push(Choice { alternative, pos, k, trace, groups, frames, outline });
+    }
+}
+
+impl Program {
+    pub fn compile(states: &[State]) -> Self {
+        let mut program = Self {
+            length_bounds: sequence_length_bounds(states),
+            ..Self::default()
+        };
+        program.root = states.iter().map(|s| program.add(s)).collect();
+        program
+    }
+
+    pub fn set_config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn get_config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn add(&mut self, state: &State) -> NodeId {
+        let kind = match state.get_block_states() {
+            None => Kind::Leaf(state.clone()),
+            Some(children) => {
+                let ids = children.iter().map(|s| self.add(s)).collect();
+                if state.is_coalesce() {
+                    Kind::Choice(ids)
+                } else {
+                    Kind::Sequence(ids)
+                }
+            }
+        };
+
+        let id = self.nodes.len();
+        let (first, repetition_can_be_empty) = self.first_of(id, &kind);
+        let (first, can_be_empty) = if state.get_max() == Some(0) {
+            (Vec::new(), true)
+        } else {
+            (first, repetition_can_be_empty || state.get_min() == 0)
+        };
+        self.nodes.push(Node { identifier: state.get_identifier(), min: state.get_min(), max: state.get_max(), kind, first, can_be_empty });
+        id
+    }
+
+    /// The leaves a single repetition of a node could start with, following skippable states along a sequence.
+    fn first_of(&self, id: NodeId, kind: &Kind) -> (Vec<NodeId>, bool) {
+        match kind {
+            Kind::Leaf(state) => match state.get_literal() {
+                Some("") => (Vec::new(), true),
+                _ => (vec![id], false),
+            },
+            Kind::Choice(options) => {
+                let first = options.iter().flat_map(|o| self.nodes[*o].first.iter().copied()).collect();
+                (first, options.iter().any(|o| self.nodes[*o].can_be_empty))
+            }
+            Kind::Sequence(children) => {
+                let mut first = Vec::new();
+                for child in children {
+                    first.extend(self.nodes[*child].first.iter().copied());
+                    if !self.nodes[*child].can_be_empty {
+                        return (first, false);
+                    }
+                }
+                (first, true)
+            }
+        }
+    }
+
+    /// Whether a node is worth trying with the next character, or with nothing left when there's none.
+    fn admits(&self, id: NodeId, next: Option<&char>) -> bool {
+        let node = &self.nodes[id];
+        node.can_be_empty || next.is_some_and(|c| node.first.iter().any(|leaf| self.leaf(*leaf).does_char_qualify(*c)))
+    }
+
+    fn leaf(&self, id: NodeId) -> &State {
+        match &self.nodes[id].kind {
+            Kind::Leaf(state) => state,
+            _ => unreachable!("only leaves end up in first sets"),
+        }
+    }
+
+    /// Matches the whole input, giving back the states that consumed it and what each one took.
+    pub fn run(&self, input: &str) -> Option<Match> {
+        let input: Vec<char> = input.chars().collect();
+        let (low, high) = self.length_bounds;
+        if input.len() < low || high.is_some_and(|high| input.len() > high) {
+            return None;
+        }
+
+        let mut run = Run::new(&input);
+        if self.config.get_disambiguation() == Disambiguation::LeftmostLongest {
+            run.outline = Some(Outline::default());
+        } else {
+            run.memo = Some(Memo::new(self));
+        }
+        let mut best: Option<(Trace, Trace)> = None;
+        let found = self.search(&mut run, &mut |run| {
+            // Leftmost-longest keeps going, holding on to the best parse so far. Anything that can't beat it gets cut
+            // off along the way.
+            let Some(outline) = run.outline.as_mut() else {
+                return true;
+            };
+            if outline.finish() {
+                best = Some((run.trace.clone(), run.groups.clone()));
+            }
+            false
+        });
+
+        let (trace, groups) = if found { (run.trace, run.groups) } else { best? };
+        Some(self.to_match(&input, &trace, &groups))
+    }
+
+    fn to_match(&self, input: &[char], trace: &[(NodeId, usize, usize)], groups: &[(NodeId, usize, usize)]) -> Match {
+        let segments = trace.iter().map(|(id, start, end)| (self.leaf(*id).clone(), input[*start..*end].iter().collect())).collect();
+        let spans = trace.iter().map(|(_, start, end)| (*start, *end)).collect();
+
+        // Later visits overwrite earlier ones, so a repeated capture ends up with its last repetition.
+        let mut captures = BTreeMap::new();
+        let mut visits: Vec<&(NodeId, usize, usize)> = trace.iter().chain(groups).collect();
+        visits.sort_by_key(|(_, start, end)| (*end, *start));
+        for (id, start, end) in visits {
+            let identifier = self.nodes[*id].identifier;
+            if identifier != 0 {
+                captures.insert(identifier, (*start, *end));
+            }
+        }
+        Match::new(segments, spans, captures)
+    }
+
+    /// Whether the whole input matches. Stops at the first parse whatever the config, since which parse wins doesn't
+    /// change the answer.
+    pub fn is_match(&self, input: &str) -> bool {
+        let input: Vec<char> = input.chars().collect();
+        let (low, high) = self.length_bounds;
+        if input.len() < low || high.is_some_and(|high| input.len() > high) {
+            return false;
+        }
+        let mut run = Run::new(&input);
+        run.memo = Some(Memo::new(self));
+        self.search(&mut run, &mut |_| true)
+    }
+
+    fn children(&self, parent: Option<NodeId>) -> &[NodeId] {
+        match parent {
+            None => &self.root,
+            Some(id) => match &self.nodes[id].kind {
+                Kind::Sequence(children) => children,
+                _ => unreachable!("only sequences have children in order"),
+            },
+        }
+    }
+
+    fn greedy(&self) -> bool {
+        self.config.get_disambiguation() != Disambiguation::Lazy
+    }
+
+    /// Backtracks through every parse in the configured order, handing each one that takes the whole input to
+    /// `accept` until it says to stop. Returns whether it did.
+    fn search(&self, run: &mut Run, accept: &mut dyn FnMut(&mut Run) -> bool) -> bool {
+        let start = run.push(Goal::Sequence(None, 0), None);
+        let mut at = Some((0, start));
+        while let Some((pos, k)) = at {
+            // Only worth remembering if there's something to backtrack to, or it could never come round again.
+            let leaf = k.is_none_or(|frame| matches!(run.frames[frame].0, Goal::Node(id) if matches!(self.nodes[id].kind, Kind::Leaf(_))));
+            if leaf && !run.choices.is_empty() && run.memo.as_mut().is_some_and(|memo| !memo.visit(&run.frames, pos, k)) {
+                at = self.backtrack(run);
+                continue;
+            }
+            at = match k {
+                None if pos == run.input.len() && accept(run) => return true,
+                None => self.backtrack(run),
+                Some(frame) => {
+                    let (goal, k) = run.frames[frame];
+                    let next = self.step(run, goal, pos, k).filter(|_| run.outline.as_mut().is_none_or(Outline::promising));
+                    next.or_else(|| self.backtrack(run))
+                }
+            };
+        }
+        false
+    }
+
+    /// Works on one goal, giving back where the match is up to afterwards, or `None` if it can't go on.
+    fn step(&self, run: &mut Run, goal: Goal, pos: usize, k: Continuation) -> Option<(usize, Continuation)> {
+        match goal {
+            Goal::Sequence(parent, from) => {
+                let children = self.children(parent);
+                match children.get(from) {
+                    None => Some((pos, k)),
+                    Some(child) => {
+                        let rest = if from + 1 < children.len() { run.push(Goal::Sequence(parent, from + 1), k) } else { k };
+                        Some((pos, run.push(Goal::Node(*child), rest)))
+                    }
+                }
+            }
+            Goal::Node(id) => {
+                let node = &self.nodes[id];
+                match &node.kind {
+                    Kind::Leaf(state) => {
+                        let (step, units) = state.longest_run(run.input[pos..].iter().copied());
+                        let longest = node.max.map_or(units, |max| units.min(max));
+                        self.lengths(run, id, step, node.min..=longest, pos, k)
+                    }
+                    // Blocks with an identifier get their whole span recorded, for captures, and leftmost-longest wants every
+                    // block's.
+                    _ if node.identifier != 0 || run.outline.is_some() => {
+                        let entry = run.outline.as_mut().map(Outline::open);
+                        let k = run.push(Goal::Group(id, pos, entry), k);
+                        self.block(run, id, pos, k)
+                    }
+                    _ => self.block(run, id, pos, k),
+                }
+            }
+            Goal::Repeat(id, option, done) => {
+                let node = &self.nodes[id];
+                let (stop, another) = (done >= node.min, node.max.is_none_or(|max| done < max));
+                match (stop, another) {
+                    (false, false) => None,
+                    (true, false) => Some((pos, k)),
+                    (false, true) => Some((pos, self.another(run, id, option, done, pos, k))),
+                    (true, true) if self.greedy() => {
+                        run.choose(Alternative::Stop, pos, k);
+                        Some((pos, self.another(run, id, option, done, pos, k)))
+                    }
+                    (true, true) => {
+                        run.choose(Alternative::Another(id, option, done), pos, k);
+                        Some((pos, k))
+                    }
+                }
+            }
+            Goal::Repeated(id, option, done, start) => {
+                // An empty repetition past the minimum gets us nowhere we haven't already been.
+                if pos == start && done >= self.nodes[id].min {
+                    return None;
+                }
+                self.step(run, Goal::Repeat(id, option, done + 1), pos, k)
+            }
+            Goal::Group(id, start, entry) => {
+                if self.nodes[id].identifier != 0 {
+                    run.groups.push((id, start, pos));
+                }
+                if let (Some(outline), Some(entry)) = (run.outline.as_mut(), entry) {
+                    outline.close(entry, pos);
+                }
+                Some((pos, k))
+            }
+        }
+    }
+
+    fn block(&self, run: &mut Run, id: NodeId, pos: usize, k: Continuation) -> Option<(usize, Continuation)> {
+        match &self.nodes[id].kind {
+            Kind::Sequence(_) => Some((pos, run.push(Goal::Repeat(id, None, 0), k))),
+            Kind::Choice(_) => self.options(run, id, 0, pos, k),
+            Kind::Leaf(_) => unreachable!("leaves aren't blocks"),
+        }
+    }
+
+    /// Takes the first option of a coalesce block from `from` on that could work, leaving the rest for later.
+    fn options(&self, run: &mut Run, id: NodeId, from: usize, pos: usize, k: Continuation) -> Option<(usize, Continuation)> {
+        let node = &self.nodes[id];
+        let Kind::Choice(options) = &node.kind else { unreachable!("only coalesce blocks have options") };
+        let next = run.input.get(pos);
+        let option = (from..options.len()).find(|option| node.min == 0 || self.admits(options[*option], next))?;
+        if option + 1 < options.len() {
+            run.choose(Alternative::Options(id, option + 1), pos, k);
+        }
+        Some((pos, run.push(Goal::Repeat(id, Some(option), 0), k)))
+    }
+
+    /// Takes the next run of a leaf, shortest first unless it's greedy, leaving the rest for later.
+    fn lengths(&self, run: &mut Run, id: NodeId, step: usize, mut lengths: RangeInclusive<usize>, pos: usize, k: Continuation) -> Option<(usize, Continuation)> {
+        let units = if self.greedy() { lengths.next_back() } else { lengths.next() }?;
+        if !lengths.is_empty() {
+            run.choose(Alternative::Lengths(id, step, lengths), pos, k);
+        }
+        let end = pos + units * step;
+        run.trace.push((id, pos, end));
+        if let Some(outline) = run.outline.as_mut() {
+            outline.ends.push(end);
+        }
+        Some((end, k))
+    }
+
+    /// One more repetition of the body, followed by whatever comes after it.
+    fn another(&self, run: &mut Run, id: NodeId, option: Option<usize>, done: usize, pos: usize, k: Continuation) -> Continuation {
+        let k = run.push(Goal::Repeated(id, option, done, pos), k);
+        match (option, &self.nodes[id].kind) {
+            (Some(option), Kind::Choice(options)) => run.push(Goal::Node(options[option]), k),
+            _ => run.push(Goal::Sequence(Some(id), 0), k),
+        }
+    }
+
+    /// Goes back to the latest choice point with something left to try, and tries it.
+    fn backtrack(&self, run: &mut Run) -> Option<(usize, Continuation)> {
+        loop {
+            let Choice { alternative, pos, k, trace, groups, frames, outline } = run.choices.pop()?;
+            run.trace.truncate(trace);
+            run.groups.truncate(groups);
+            run.frames.truncate(frames);
+            if let Some(memo) = run.memo.as_mut() {
+                memo.frames.truncate(frames);
+            }
+            if let Some(run_outline) = run.outline.as_mut() {
+                run_outline.rewind(outline);
+            }
+            let next = match alternative {
+                Alternative::Lengths(id, step, lengths) => self.lengths(run, id, step, lengths, pos, k),
+                Alternative::Options(id, from) => self.options(run, id, from, pos, k),
+                Alternative::Another(id, option, done) => Some((pos, self.another(run, id, option, done, pos, k))),
+                Alternative::Stop => Some((pos, k)),
+            };
+            if next.is_some() {
+                return next;
+            }
+        }
+    }
+}
+
+/// How far along a repetition is, which is all that decides how it can carry on. Before the minimum that's the count
+/// so far. After it, it's how many more repetitions are allowed, `None` once that's more than the whole input could
+/// hold, since every one of them has to consume something.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+enum Phase {
+    Required(usize),
+    Optional(Option<usize>),
+}
+
+/// Work a parse still has to get through, like a Goal but with no positions in it. Two parses at the same position
+/// with the same work left carry on in exactly the same ways, so they only need counting once.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+enum Pending {
+    Sequence(Option<NodeId>, usize),
+    Node(NodeId),
+    Repeat(NodeId, Option<usize>, Phase),
+    /// A repetition is over, and whether it consumed anything along the way.
+    Repeated(NodeId, Option<usize>, Phase, bool),
+    Group(NodeId),
+}
+
+/// Parse counts for one input, worked out going forward a position at a time. Each stack of pending work (top last)
+/// gets a number, and what gets counted at each position is the stacks about to consume something there: a leaf on
+/// top that takes at least one character, or nothing left at all. Whatever happens without consuming anything, like
+/// starting a block or taking an empty run, is spread through in order at each position, off worklists rather than the
+/// call stack, since something like `5000(.N)` goes thousands of steps without consuming anything. All the arithmetic
+/// saturates.
+struct Counter<'p> {
+    program: &'p Program,
+    input: Vec<char>,
+    stacks: Vec<Vec<Pending>>,
+    numbers: HashMap<Vec<Pending>, usize>,
+    // Where each stack goes without consuming anything, in the order they get listed.
+    skips: HashMap<usize, Rc<Vec<usize>>>,
+    // The stacks about to consume something at each position, kept only when the parses are going to be listed.
+    reached: Option<Vec<Vec<usize>>>,
+    // How many ways each of those can finish off the input from its position. Only worked out going back over the
+    // positions once a parse is actually asked for.
+    remaining: Option<Vec<HashMap<usize, u128>>>,
+    // The same for any stack at all, filled in as they get asked about.
+    ways: Vec<HashMap<usize, u128>>,
+}
+
+impl<'p> Counter<'p> {
+    fn new(program: &'p Program, input: &str) -> Self {
+        Self {
+            program,
+            input: input.chars().collect(),
+            stacks: Vec::new(),
+            numbers: HashMap::new(),
+            skips: HashMap::new(),
+            reached: None,
+            remaining: None,
+            ways: Vec::new(),
+        }
+    }
+
+    fn number(&mut self, stack: Vec<Pending>) -> usize {
+        if let Some(number) = self.numbers.get(&stack) {
+            return *number;
+        }
+        self.stacks.push(stack.clone());
+        self.numbers.insert(stack, self.stacks.len() - 1);
+        self.stacks.len() - 1
+    }
+
+    /// The stack before anything has happened.
+    fn start(&mut self) -> usize {
+        let stack = if self.program.root.is_empty() { Vec::new() } else { vec![Pending::Sequence(None, 0)] };
+        self.number(stack)
+    }
+
+    fn phase(&self, id: NodeId, done: usize) -> Phase {
+        let node = &self.program.nodes[id];
+        if done < node.min {
+            return Phase::Required(done);
+        }
+        Phase::Optional(node.max.map(|max| max - done).filter(|left| *left <= self.input.len()))
+    }
+
+    /// The phase after one more repetition.
+    fn next_phase(&self, id: NodeId, phase: Phase) -> Phase {
+        match phase {
+            Phase::Required(done) => self.phase(id, done + 1),
+            Phase::Optional(left) => Phase::Optional(left.map(|left| left - 1)),
+        }
+    }
+
+    /// The leaf on top of the stack, if that's what's there.
+    fn leaf_on_top(&self, stack: usize) -> Option<NodeId> {
+        match self.stacks[stack].last() {
+            Some(Pending::Node(id)) if matches!(self.program.nodes[*id].kind, Kind::Leaf(_)) => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// Whether a leaf can take an empty run, wherever it is.
+    fn can_skip(&self, id: NodeId) -> bool {
+        let node = &self.program.nodes[id];
+        let empty_literal = matches!(&node.kind, Kind::Leaf(state) if state.get_literal() == Some(""));
+        node.min == 0 || (empty_literal && node.max.is_none_or(|max| max >= node.min))
+    }
+
+    /// The stack with its top taken off, and the top.
+    fn pop(&mut self, stack: usize) -> (Vec<Pending>, Pending) {
+        let mut rest = self.stacks[stack].clone();
+        let top = rest.pop().unwrap();
+        (rest, top)
+    }
+
+    /// Where a stack with something other than a leaf on top can go without consuming anything, in the order they get
+    /// listed: fewer repetitions and earlier options first.
+    fn successors(&mut self, stack: usize) -> Vec<usize> {
+        let (rest, top) = self.pop(stack);
+        let with = |goals: &[Pending]| rest.iter().chain(goals).copied().collect::<Vec<Pending>>();
+        let nodes = &self.program.nodes;
+        let stacks = match top {
+            Pending::Sequence(parent, from) => {
+                let children = self.program.children(parent);
+                match children.get(from) {
+                    None => vec![with(&[])],
+                    Some(child) if from + 1 < children.len() => vec![with(&[Pending::Sequence(parent, from + 1), Pending::Node(*child)])],
+                    Some(child) => vec![with(&[Pending::Node(*child)])],
+                }
+            }
+            Pending::Node(id) => {
+                let group: &[Pending] = if nodes[id].identifier != 0 { &[Pending::Group(id)] } else { &[] };
+                let phase = self.phase(id, 0);
+                match &nodes[id].kind {
+                    Kind::Choice(options) => (0..options.len()).map(|option| with(&[group, &[Pending::Repeat(id, Some(option), phase)]].concat())).collect(),
+                    _ => vec![with(&[group, &[Pending::Repeat(id, None, phase)]].concat())],
+                }
+            }
+            Pending::Repeat(id, option, phase) => {
+                let mut stacks = Vec::new();
+                if matches!(phase, Phase::Optional(_)) {
+                    stacks.push(with(&[]));
+                }
+                if phase != Phase::Optional(Some(0)) {
+                    let body = match (option, &nodes[id].kind) {
+                        (Some(option), Kind::Choice(options)) => Pending::Node(options[option]),
+                        _ => Pending::Sequence(Some(id), 0),
+                    };
+                    stacks.push(with(&[Pending::Repeated(id, option, phase, false), body]));
+                }
+                stacks
+            }
+            // An empty repetition past the minimum gets us nowhere we haven't already been.
+            Pending::Repeated(_, _, Phase::Optional(_), false) => Vec::new(),
+            Pending::Repeated(id, option, phase, _) => vec![with(&[Pending::Repeat(id, option, self.next_phase(id, phase))])],
+            Pending::Group(_) => vec![with(&[])],
+        };
+        stacks.into_iter().map(|stack| self.number(stack)).collect()
+    }
+
+    /// The stack once something has been consumed, so every repetition underway has consumed something too.
+    fn consumed(&mut self, stack: Vec<Pending>) -> usize {
+        let stack = stack
+            .into_iter()
+            .map(|pending| match pending {
+                Pending::Repeated(id, option, phase, _) => Pending::Repeated(id, option, phase, true),
+                other => other,
+            })
+            .collect();
+        self.number(stack)
+    }
+
+    /// Whether the stack has to wait for the input: a leaf on top, or nothing left at all.
+    fn settled(&self, stack: usize) -> bool {
+        self.stacks[stack].is_empty() || self.leaf_on_top(stack).is_some()
+    }
+
+    /// Where a stack goes next without consuming anything. A leaf on top can only get skipped, when it takes an empty
+    /// run.
+    fn skips(&mut self, stack: usize) -> Rc<Vec<usize>> {
+        if let Some(skips) = self.skips.get(&stack) {
+            return skips.clone();
+        }
+        let skips = match self.leaf_on_top(stack) {
+            _ if self.stacks[stack].is_empty() => Vec::new(),
+            Some(leaf) if self.can_skip(leaf) => {
+                let rest = self.pop(stack).0;
+                vec![self.number(rest)]
+            }
+            Some(_) => Vec::new(),
+            None => self.successors(stack),
+        };
+        let skips = Rc::new(skips);
+        self.skips.insert(stack, skips.clone());
+        skips
+    }
+
+    /// Every stack the sources get to without consuming anything, each one before everywhere it goes. Nothing gets
+    /// followed past the stacks already known.
+    fn order(&mut self, sources: Vec<usize>, known: &HashMap<usize, u128>) -> Vec<usize> {
+        let (mut seen, mut order) = (HashSet::new(), Vec::new());
+        for source in sources {
+            if known.contains_key(&source) || !seen.insert(source) {
+                continue;
+            }
+            let mut work = vec![(source, 0)];
+            while let Some((stack, next)) = work.pop() {
+                match self.skips(stack).get(next) {
+                    None => order.push(stack),
+                    Some(to) => {
+                        work.push((stack, next + 1));
+                        if !known.contains_key(to) && seen.insert(*to) {
+                            work.push((*to, 0));
+                        }
+                    }
+                }
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Carries the counts of the stacks at a position through everything that doesn't consume anything, giving the
+    /// stacks about to consume something with how many ways there are to be there.
+    fn spread(&mut self, mut counts: HashMap<usize, u128>) -> Vec<(usize, u128)> {
+        let mut settled = Vec::new();
+        for stack in self.order(counts.keys().copied().collect(), &HashMap::new()) {
+            let Some(count) = counts.get(&stack).copied() else { continue };
+            if self.settled(stack) {
+                settled.push((stack, count));
+            }
+            for to in self.skips(stack).iter() {
+                let total = counts.entry(*to).or_insert(0);
+                *total = total.saturating_add(count);
+            }
+        }
+        settled
+    }
+
+    /// Where the leaf on top can get to by consuming at least one character, shortest runs first.
+    fn consume(&mut self, stack: usize, pos: usize) -> Vec<(usize, usize)> {
+        let Some(id) = self.leaf_on_top(stack) else {
+            return Vec::new();
+        };
+        let node = &self.program.nodes[id];
+        let Kind::Leaf(state) = &node.kind else { unreachable!() };
+        let (step, units) = state.longest_run(self.input[pos..].iter().copied());
+        let longest = node.max.map_or(units, |max| units.min(max));
+        if step == 0 {
+            return Vec::new();
+        }
+
+        let rest = self.pop(stack).0;
+        let next = self.consumed(rest);
+        (node.min.max(1)..=longest).map(|units| (pos + units * step, next)).collect()
+    }
+
+    fn total(&mut self) -> u128 {
+        let end = self.input.len();
+        let (low, high) = self.program.length_bounds;
+        if end < low || high.is_some_and(|high| end > high) {
+            return 0;
+        }
+
+        let mut ahead: Vec<HashMap<usize, u128>> = vec![HashMap::new(); end + 1];
+        let start = self.start();
+        ahead[0].insert(start, 1);
+
+        let mut total = 0u128;
+        for pos in 0..=end {
+            let here = self.spread(std::mem::take(&mut ahead[pos]));
+            if let Some(reached) = self.reached.as_mut() {
+                reached[pos] = here.iter().map(|(stack, _)| *stack).collect();
+            }
+            for (stack, count) in here {
+                if self.stacks[stack].is_empty() && pos == end {
+                    total = total.saturating_add(count);
+                }
+                for (next_pos, next) in self.consume(stack, pos) {
+                    let entry = ahead[next_pos].entry(next).or_insert(0);
+                    *entry = entry.saturating_add(count);
+                }
+            }
+        }
+        total
+    }
+
+    /// Goes back over the positions from the end, working out how many ways each reached stack can finish.
+    fn count_remaining(&mut self) {
+        let end = self.input.len();
+        let reached = self.reached.take().unwrap_or_default();
+        self.remaining = Some(vec![HashMap::new(); end + 1]);
+        self.ways = vec![HashMap::new(); end + 1];
+        for (pos, stacks) in reached.iter().enumerate().rev() {
+            for stack in stacks {
+                let mut count = u128::from(self.stacks[*stack].is_empty() && pos == end);
+                for (next_pos, next) in self.consume(*stack, pos) {
+                    count = count.saturating_add(self.ways(next, next_pos));
+                }
+                self.remaining.as_mut().unwrap()[pos].insert(*stack, count);
+            }
+        }
+        self.reached = Some(reached);
+    }
+
+    /// How many ways any stack can finish off the input from a position. Everything it gets to without consuming
+    /// anything is worked out first, after wherever that goes.
+    fn ways(&mut self, stack: usize, pos: usize) -> u128 {
+        let mut ways = std::mem::take(&mut self.ways[pos]);
+        for current in self.order(vec![stack], &ways).into_iter().rev() {
+            let own = if self.settled(current) { self.remaining.as_ref().unwrap()[pos].get(&current).copied().unwrap_or(0) } else { 0 };
+            let count = self.skips(current).iter().fold(own, |total, to| total.saturating_add(ways[to]));
+            ways.insert(current, count);
+        }
+        let count = ways[&stack];
+        self.ways[pos] = ways;
+        count
+    }
+
+    /// Picks out parse number `rank`, walking forward from the start and going whichever way has that many parses
+    /// behind it, in the same order the successors are listed in.
+    fn unrank(&mut self, mut rank: u128) -> (Trace, Trace) {
+        let (mut trace, mut groups, mut open) = (Vec::new(), Vec::new(), Vec::new());
+        let (mut stack, mut pos) = (self.start(), 0);
+        while let Some(top) = self.stacks[stack].last().copied() {
+            let mut moves: Vec<(usize, usize)> = self.skips(stack).iter().map(|next| (pos, *next)).collect();
+            moves.extend(self.consume(stack, pos));
+
+            let mut chosen = None;
+            for (next_pos, next) in moves {
+                let count = self.ways(next, next_pos);
+                if rank < count {
+                    chosen = Some((next_pos, next));
+                    break;
+                }
+                rank -= count;
+            }
+            let Some((next_pos, next)) = chosen else {
+                unreachable!("ranks only go up to the total");
+            };
+
+            match top {
+                Pending::Node(id) if self.leaf_on_top(stack).is_some() => trace.push((id, pos, next_pos)),
+                Pending::Node(id) if self.program.nodes[id].identifier != 0 => open.push(pos),
+                Pending::Group(id) => groups.push((id, open.pop().unwrap(), pos)),
+                _ => {}
+            }
+            (stack, pos) = (next, next_pos);
+        }
+        (trace, groups)
+    }
+}
+
+/// Every parse of an input, one Match at a time. Each one is picked straight out of the counts, so nothing gets
+/// enumerated ahead of time. Past `u128::MAX` parses the counts saturate and the rest never come out.
+pub struct Parses<'p> {
+    counter: Counter<'p>,
+    total: u128,
+    next: u128,
+}
+
+impl Iterator for Parses<'_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        if self.next >= self.total {
+            return None;
+        }
+        if self.counter.remaining.is_none() {
+            self.counter.count_remaining();
+        }
+        let (trace, groups) = self.counter.unrank(self.next);
+        self.next += 1;
+        Some(self.counter.program.to_match(&self.counter.input, &trace, &groups))
+    }
+}
+
+impl Program {
+    /// How many different ways the input can be split up between the states. Repetitions past the minimum have to
+    /// consume something, otherwise there'd be no end to them. Saturates at `u128::MAX`.
+    pub fn count_runs(&self, input: &str) -> u128 {
+        Counter::new(self, input).total()
+    }
+
+    /// Every parse `count_runs` counted, shorter runs and fewer repetitions first.
+    pub fn all_runs(&self, input: &str) -> Parses<'_> {
+        let mut counter = Counter::new(self, input);
+        counter.reached = Some(vec![Vec::new(); counter.input.len() + 1]);
+        let total = counter.total();
+        Parses { counter, total, next: 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::engine::Engine;
+    use crate::regex::rule::PatternType;
+    use crate::regex::testing;
+
+    fn compile(text: &str) -> (Vec<State>, Program) {
+        let states = testing::compile(text).get_states().clone();
+        let program = Program::compile(&states);
+        (states, program)
+    }
+
+    #[test]
+    fn agrees_with_the_engine() {
+        let texts = ["3N1\"-\"2N1\"-\"4N", "1.3(1A,1N1\".\")", ".(2N,1\"x\"1A).E", "2(1\"ab\"i,1N)1.2(.2A1N)", "1(2N,3A)", "1U.L", ".N", "2(.N)", ".N.E1(1\"a\",1\"ab\").E"];
+        let inputs = ["", "1", "123-45-6789", "a1.b", "x12xA...", "AB12a1", "AB", "1.1.1.", "ab7a1b2", "Abc", "12345", "12abc"];
+        for disambiguation in [Disambiguation::Lazy, Disambiguation::Greedy, Disambiguation::LeftmostLongest] {
+            let config = EngineConfig::new().set_disambiguation(disambiguation);
+            for text in texts {
+                let (states, program) = compile(text);
+                let program = program.set_config(config);
+                for input in inputs {
+                    let mut engine = Engine::new(input.to_string(), states.clone()).set_config(config);
+                    let expected = if engine.process().unwrap() { engine.extract_results() } else { None };
+                    let strings = |segments: Option<Vec<(State, String)>>| segments.map(|s| s.into_iter().map(|(_, s)| s).collect::<Vec<String>>());
+                    let found = program.run(input).map(Match::into_segments);
+                    assert_eq!(strings(found), strings(expected), "{} on {:?}, {:?}", text, input, disambiguation);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn repetition_stays_a_counter() {
+        // One node for the block and one for what's inside, however many times it repeats.
+        let block = State::new_block(1, Some(1000), vec![State::new(1, Some(1), vec![PatternType::Numeric])]);
+        let program = Program::compile(&[block]);
+        assert_eq!(program.node_count(), 2);
+        assert!(program.is_match(&"7".repeat(1000)));
+        assert!(!program.is_match(&"7".repeat(1001)));
+        assert!(!program.is_match(""));
+
+        // Each repetition of a group gets its own entry in the results.
+        let (_, program) = compile("3(1A,1N)");
+        let found = program.run("a1b").unwrap();
+        assert_eq!(found.get_segments().iter().map(|(_, s)| s.as_str()).collect::<Vec<&str>>(), vec!["a", "1", "b"]);
+        assert_eq!(found.get_capture(1), Some((0, 3)));
+    }
+
+    #[test]
+    fn long_inputs_stay_off_the_call_stack() {
+        // A repeated group over a hundred thousand characters, on whatever stack the test gets.
+        let long = "7".repeat(100_000);
+        for disambiguation in [Disambiguation::Lazy, Disambiguation::Greedy] {
+            let (_, program) = compile(".(1N)");
+            let program = program.set_config(EngineConfig::new().set_disambiguation(disambiguation));
+            assert_eq!(program.run(&long).unwrap().get_segments().len(), 100_000);
+            assert!(!program.is_match(&(long.clone() + "x")));
+        }
+        let (_, program) = compile(".(1N.A)1\"x\"");
+        assert!(program.is_match(&("7".repeat(50_000) + "x")));
+
+        // Leftmost-longest would look at all 2^99 ways of cutting this up to pick one, but a yes or no is the first.
+        let (_, program) = compile(".(1.E)");
+        let program = program.set_config(EngineConfig::new().set_disambiguation(Disambiguation::LeftmostLongest));
+        assert!(program.is_match(&"x".repeat(100)));
+    }
+
+    #[test]
+    fn failures_take_polynomial_time() {
+        // Every way of cutting up the digits fails, and there are 2^99 of them for the first one.
+        let started = std::time::Instant::now();
+        let digits = "7".repeat(100);
+        for text in [".(.N)1\"x\"", ".(.N).(.N)1\"x\"", ".N.N.N.N1\"x\""] {
+            for disambiguation in [Disambiguation::Lazy, Disambiguation::Greedy] {
+                let (_, program) = compile(text);
+                let program = program.set_config(EngineConfig::new().set_disambiguation(disambiguation));
+                assert!(!program.is_match(&(digits.clone() + "y")));
+                assert!(program.run(&(digits.clone() + "y")).is_none());
+                assert!(program.is_match(&(digits.clone() + "x")));
+            }
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(10), "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn leftmost_longest_goes_by_state() {
+        let longest = EngineConfig::new().set_disambiguation(Disambiguation::LeftmostLongest);
+        let group = |text: &str, input: &str| compile(text).1.set_config(longest).run(input).unwrap().get_capture(1);
+        // The group is one state, so it takes all it can before the state after it gets anything.
+        assert_eq!(group(".(1N).N", "12"), Some((0, 2)));
+        assert_eq!(group(".(2N).N", "1234"), Some((0, 4)));
+        assert_eq!(group("1.3(1N).N", "123"), Some((0, 3)));
+        assert_eq!(group("1.2(1N).N", "123"), Some((0, 2)));
+
+        // Once the first state has everything, nothing where it has less is worth finishing.
+        let (_, program) = compile(".E.E.E.E.E");
+        let found = program.set_config(longest).run(&"x".repeat(200)).unwrap();
+        assert_eq!(found.get_spans()[0], (0, 200));
+    }
+
+    // Counts by trying every parse, the slow way.
+    fn brute_force(program: &Program, input: &str) -> u128 {
+        let input: Vec<char> = input.chars().collect();
+        let mut count = 0;
+        program.search(&mut Run::new(&input), &mut |_| {
+            count += 1;
+            false
+        });
+        count
+    }
+
+    #[test]
+    fn counts_and_lists_parses() {
+        let (_, program) = compile(".E1\"-\".E");
+        assert_eq!(program.count_runs("a-b-c"), 2);
+        assert_eq!(program.count_runs("abc"), 0);
+        let (_, program) = compile(".N.N");
+        assert_eq!(program.count_runs("123"), 4);
+        assert_eq!(program.count_runs(&"1".repeat(500)), 501);
+
+        let texts = [".N.N", "2(.N)", ".(1A,.N).E", "1.3(1A,1N1\".\")", ".E1(1\"a\",1\"ab\").E", "2(1\"ab\"i,1N)1.2(.2A1N)", ".(.N,.A)"];
+        let inputs = ["", "1", "12", "a1.b", "ab7a1b2", "1a2b3c", "aab", "12ab34"];
+        for text in texts {
+            let (_, program) = compile(text);
+            for input in inputs {
+                let count = program.count_runs(input);
+                assert_eq!(count, brute_force(&program, input), "{} on {:?}", text, input);
+
+                let parses: Vec<Vec<(usize, usize)>> = program.all_runs(input).map(|found| found.get_spans().clone()).collect();
+                assert_eq!(parses.len() as u128, count, "{} on {:?}", text, input);
+                for (i, spans) in parses.iter().enumerate() {
+                    assert!(!parses[..i].contains(spans), "{} on {:?} repeats a parse", text, input);
+                }
+                for found in program.all_runs(input) {
+                    let text: String = found.get_segments().iter().map(|(_, s)| s.as_str()).collect();
+                    assert_eq!(text, input);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parse_counts_saturate() {
+        // Any way of cutting the input into pieces works, so there are 2^(n-1) of them.
+        let (_, program) = compile(".(1.E)");
+        assert_eq!(program.count_runs(&"x".repeat(100)), 1 << 99);
+        assert_eq!(program.count_runs(&"x".repeat(130)), u128::MAX);
+        assert!(program.all_runs("xyz").count() > 0);
+    }
+
+    #[test]
+    fn empty_repetitions_stay_off_the_call_stack() {
+        // Thousands of repetitions that can each come up empty, all gone through without consuming anything.
+        let (_, program) = compile("2000(.N)");
+        assert_eq!(program.count_runs(""), 1);
+        assert_eq!(program.all_runs("").count(), 1);
+        // Both digits in one repetition, or each in its own.
+        assert_eq!(program.count_runs("12"), 2000 + 2000 * 1999 / 2);
+        // Every repetition shows up in a parse, empty or not.
+        assert_eq!(program.all_runs("12").next().unwrap().get_spans().len(), 2000);
+    }
+}
@@ -1,41 +1,183 @@
-#[derive(Clone, Copy, Debug)]
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+use super::dialect::Dialect;
+use super::helpers::{case_variants, normalize_ranges, ranges_contain, repeat_bounds, sequence_length_bounds};
+use super::table::PatternTable;
+
+/// How the pattern codes sort characters into classes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Classification {
+    /// Only ASCII characters belong to any class other than E, like an M server running in M mode.
+    Ascii,
+    /// ISO-8859-1, so accented Western European letters count as A/U/L. Anything above U+00FF is only E.
+    Latin1,
+    /// Letters, cases, control and punctuation characters go by Unicode.
+    Unicode,
+}
+
+/// Whatever the default dialect uses, so states made by hand classify the same way as ones out of the Builder.
+impl Default for Classification {
+    fn default() -> Self {
+        Dialect::default().classification()
+    }
+}
+
+/// A character class supplied by whoever embeds the engine, for things no table can express. The name is what shows up
+/// when the pattern gets printed.
+#[derive(Clone)]
+pub struct CharPredicate {
+    name: String,
+    test: Arc<dyn Fn(char) -> bool + Send + Sync>,
+}
+
+impl CharPredicate {
+    pub fn new<F>(name: &str, test: F) -> Self
+    where
+        F: Fn(char) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            test: Arc::new(test),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn test(&self, character: char) -> bool {
+        (self.test)(character)
+    }
+}
+
+/// Two predicates are only the same if they share the same closure, the name alone doesn't prove anything.
+impl PartialEq for CharPredicate {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && Arc::ptr_eq(&self.test, &other.test)
+    }
+}
+
+impl fmt::Debug for CharPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CharPredicate({:?})", self.name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum PatternType {
     Alphabetic,
     Numeric,
     Everything,
     Specific(char),
+    Uppercase,
+    Lowercase,
+    Punctuation,
+    Control,
+    Not(Box<PatternType>),
+    AnyOf(Vec<PatternType>),
+    Custom(char), // User-defined code, looked up in the state's pattern table.
+    Range(char, char), // Inclusive on both ends.
+    Set(Vec<(char, char)>), // Sorted and merged ranges, see PatternType::new_set.
+    Predicate(CharPredicate),
+    Literal(String), // Whole string, consumed in one step. As a character class it only speaks for the first character.
 }
 
 impl PatternType {
+    /// Maps an M pattern code letter (A, C, E, L, N, P, U) to its pattern type. Case does not matter.
+    pub fn from_code(code: char) -> Option<Self> {
+        match code.to_ascii_uppercase() {
+            'A' => Some(Self::Alphabetic),
+            'C' => Some(Self::Control),
+            'E' => Some(Self::Everything),
+            'L' => Some(Self::Lowercase),
+            'N' => Some(Self::Numeric),
+            'P' => Some(Self::Punctuation),
+            'U' => Some(Self::Uppercase),
+            _ => None,
+        }
+    }
+
+    /// Builds a Set out of any mix of ranges, sorting and merging them so lookups can binary search.
+    pub fn new_set(ranges: Vec<(char, char)>) -> Self {
+        Self::Set(normalize_ranges(ranges))
+    }
+
+    /// Shorthand for a Predicate pattern type.
+    pub fn predicate<F>(name: &str, test: F) -> Self
+    where
+        F: Fn(char) -> bool + Send + Sync + 'static,
+    {
+        Self::Predicate(CharPredicate::new(name, test))
+    }
+
     pub fn is_of_type(&self, character: char) -> bool {
-        match self {
-            Self::Alphabetic => {
-                if character.is_alphabetic() {
-                    return true;
-                } else {
-                    return false;
-                }
-            }
+        self.is_of_type_in(character, Classification::Unicode)
+    }
 
-            Self::Numeric => {
-                if character.is_digit(10) {
-                    return true;
-                } else {
-                    return false;
-                }
-            }
+    pub fn is_of_type_in(&self, character: char, classification: Classification) -> bool {
+        self.is_of_type_with(character, classification, None)
+    }
 
-            Self::Everything => {
-                true
-            }
+    /// Same as is_of_type_in, but user-defined codes get looked up in the table. Without one they match nothing.
+    pub fn is_of_type_with(&self, character: char, classification: Classification, table: Option<&PatternTable>) -> bool {
+        self.classify(character, classification, table, false)
+    }
 
-            Self::Specific(c) => {
-                if character == *c {
-                    return true;
-                } else {
-                    return false;
-                }
+    /// Same as is_of_type_with, except specific characters, ranges, sets and literals don't care about case. The codes
+    /// are left alone, U and L still mean upper and lower case.
+    pub fn is_of_type_ignoring_case(&self, character: char, classification: Classification, table: Option<&PatternTable>) -> bool {
+        self.classify(character, classification, table, true)
+    }
+
+    fn classify(&self, character: char, classification: Classification, table: Option<&PatternTable>, fold_case: bool) -> bool {
+        // Only folding case needs the other variants worked out, everything else checks the character as it is.
+        let any_variant = |accepts: &dyn Fn(char) -> bool| if fold_case { case_variants(character).into_iter().any(accepts) } else { accepts(character) };
+        match self {
+            Self::Everything => true,
+            Self::Specific(c) => any_variant(&|v| v == *c),
+            Self::Not(pattern) => !pattern.classify(character, classification, table, fold_case),
+            Self::AnyOf(patterns) => patterns.iter().any(|p| p.classify(character, classification, table, fold_case)),
+            Self::Custom(code) => table.is_some_and(|t| t.contains(*code, character)),
+            Self::Range(low, high) => any_variant(&|v| *low <= v && v <= *high),
+            Self::Set(ranges) => any_variant(&|v| ranges_contain(ranges, v)),
+            Self::Predicate(predicate) => predicate.test(character),
+            Self::Literal(text) => text.chars().next().is_some_and(|first| any_variant(&|v| v == first)),
+            _ => match classification {
+                Classification::Ascii => character.is_ascii() && self.is_of_latin1_type(character),
+                Classification::Latin1 => (character as u32) <= 0xFF && self.is_of_latin1_type(character),
+                Classification::Unicode => self.is_of_unicode_type(character),
+            },
+        }
+    }
+
+    /// The code classes over the first 256 code points, which for the ASCII half is just the standard M definition.
+    fn is_of_latin1_type(&self, character: char) -> bool {
+        let upper = matches!(character, 'A'..='Z' | '\u{c0}'..='\u{d6}' | '\u{d8}'..='\u{de}');
+        let lower = matches!(character, 'a'..='z' | '\u{df}'..='\u{f6}' | '\u{f8}'..='\u{ff}');
+        match self {
+            Self::Alphabetic => upper || lower,
+            Self::Uppercase => upper,
+            Self::Lowercase => lower,
+            Self::Numeric => character.is_ascii_digit(),
+            Self::Control => matches!(character, '\u{0}'..='\u{1f}' | '\u{7f}'..='\u{9f}'),
+            // M counts the space as punctuation, Latin-1 adds the symbols from U+00A0 up plus the multiply and divide signs.
+            Self::Punctuation => {
+                matches!(character, ' ' | '\u{a0}'..='\u{bf}' | '\u{d7}' | '\u{f7}') || character.is_ascii_punctuation()
             }
+            _ => false,
+        }
+    }
+
+    fn is_of_unicode_type(&self, character: char) -> bool {
+        match self {
+            Self::Alphabetic => character.is_alphabetic(),
+            Self::Numeric => character.is_ascii_digit(),
+            Self::Uppercase => character.is_uppercase(),
+            Self::Lowercase => character.is_lowercase(),
+            Self::Control => character.is_control(),
+            Self::Punctuation => !character.is_alphanumeric() && !character.is_control(),
+            _ => false,
         }
     }
 }
@@ -48,6 +190,39 @@ pub struct State {
     patterns: Vec<PatternType>, // The aim of this is to provide some OR functionality.
     block: Option<Vec<State>>,
     coalesce: bool,
+    classification: Classification,
+    table: Option<Arc<PatternTable>>,
+    fold_case: bool,
+    first: Arc<OnceLock<FirstChars>>, // Worked out the first time someone asks, and shared between clones.
+}
+
+/// What a state can start with. The leaves are the plain states that could take the first character, following the
+/// chain of states that can be skipped, along with whether one repetition, or the whole state, can come up empty.
+#[derive(Clone, Debug, Default)]
+pub struct FirstChars {
+    leaves: Vec<State>,
+    repetition_can_be_empty: bool,
+    can_be_empty: bool,
+}
+
+impl FirstChars {
+    /// Whether a repetition of the state can start with the character.
+    pub fn can_start_with(&self, character: char) -> bool {
+        self.leaves.iter().any(|leaf| leaf.does_char_qualify(character))
+    }
+
+    /// Whether the state is worth trying with this character next, or with the input used up when there's none.
+    pub fn admits(&self, character: Option<char>) -> bool {
+        self.can_be_empty || character.is_some_and(|c| self.can_start_with(c))
+    }
+
+    pub fn repetition_can_be_empty(&self) -> bool {
+        self.repetition_can_be_empty
+    }
+
+    pub fn can_be_empty(&self) -> bool {
+        self.can_be_empty
+    }
 }
 
 impl State {
@@ -60,9 +235,18 @@ impl State {
             patterns,
             block: None,
             coalesce: false,
+            classification: Classification::default(),
+            table: None,
+            fold_case: false,
+            first: Arc::default(),
         }
     }
 
+    /// A state for a quoted string. The counts apply to the whole string, so `new_literal(2, Some(2), "AB")` is ABAB.
+    pub fn new_literal(min: usize, max: Option<usize>, text: &str) -> Self {
+        Self::new(min, max, vec![PatternType::Literal(text.to_string())])
+    }
+
     pub fn new_block(min: usize, max: Option<usize>, states: Vec<State>) -> Self {
         Self {
             identifier: 0,
@@ -71,6 +255,10 @@ impl State {
             patterns: Vec::new(),
             block: Some(states),
             coalesce: false,
+            classification: Classification::default(),
+            table: None,
+            fold_case: false,
+            first: Arc::default(),
         }
     }
 
@@ -80,6 +268,53 @@ impl State {
         output
     }
 
+    /// A group the way the Builder reads `2(1A,1N)`: a block around a coalesce that picks once, so every repetition
+    /// picks its own option.
+    pub fn new_group(min: usize, max: Option<usize>, options: Vec<State>) -> Self {
+        Self::new_block(min, max, vec![Self::new_coalesce_block(1, Some(1), options)])
+    }
+
+    /// Wraps one option of a group so the coalesce can treat it as a single state. A single state needs no wrapping.
+    pub fn new_option(mut states: Vec<State>) -> Self {
+        if states.len() == 1 {
+            states.pop().unwrap()
+        } else {
+            Self::new_block(1, Some(1), states)
+        }
+    }
+
+    /// The options, if the state is shaped like new_group makes them.
+    pub fn get_group_options(&self) -> Option<&Vec<State>> {
+        if self.coalesce {
+            return None;
+        }
+        match self.block.as_deref()? {
+            [only] if only.coalesce && only.min == 1 && only.max == Some(1) => only.get_block_states(),
+            _ => None,
+        }
+    }
+
+    /// The state repeated `min` to `max` times over, as a single state, when the counts multiply cleanly. Repeating `k`
+    /// copies of something that repeats `a..b` times covers every count from `min*a` to `max*b` when `a` is 0 or 1, or
+    /// when both counts are exact. A coalesce picks once for all its repetitions, so it never folds.
+    pub fn fold_counts(&self, min: usize, max: Option<usize>) -> Option<State> {
+        if self.coalesce {
+            return None;
+        }
+
+        let exact = Some(min) == max && Some(self.min) == self.max;
+        if self.min > 1 && !exact {
+            return None;
+        }
+
+        let total_max = match (max, self.max) {
+            (Some(0), _) | (_, Some(0)) => Some(0),
+            (Some(outer), Some(inner)) => Some(outer.checked_mul(inner)?),
+            _ => None,
+        };
+        Some(self.clone().set_bounds(min.checked_mul(self.min)?, total_max))
+    }
+
     pub fn nth_coalesce_option(&self, n: usize) -> Result<State, &'static str> {
         if n >= self.block_size().ok_or("Not a valid block type state.")? {
             Err("Invalid indices.")
@@ -90,12 +325,56 @@ impl State {
         }
     }
 
+    /// Changes how many times the state repeats, keeping everything else about it.
+    pub fn set_bounds(mut self, min: usize, max: Option<usize>) -> Self {
+        self.min = min;
+        self.max = max;
+        self.first = Arc::default();
+        self
+    }
+
     /// Useful when dealing with categorizing states when constructing a parser.
     pub fn set_identifier(mut self, new_value: usize) -> Self {
         self.identifier = new_value;
         self
     }
 
+    /// Sets how the patterns classify characters, for this state and everything inside of it.
+    pub fn set_classification(mut self, classification: Classification) -> Self {
+        self.classification = classification;
+        self.first = Arc::default();
+        self.block = self.block.map(|states| states.into_iter().map(|s| s.set_classification(classification)).collect());
+        self
+    }
+
+    /// Gives the state, and everything inside of it, the table its user-defined codes come from.
+    pub fn set_pattern_table(mut self, table: Arc<PatternTable>) -> Self {
+        self.block = self.block.map(|states| states.into_iter().map(|s| s.set_pattern_table(table.clone())).collect());
+        self.table = Some(table);
+        self.first = Arc::default();
+        self
+    }
+
+    pub fn get_pattern_table(&self) -> Option<&PatternTable> {
+        self.table.as_deref()
+    }
+
+    /// Makes specific characters, literals, ranges and sets ignore case, for this state and everything inside of it.
+    pub fn set_case_insensitive(mut self, fold_case: bool) -> Self {
+        self.fold_case = fold_case;
+        self.first = Arc::default();
+        self.block = self.block.map(|states| states.into_iter().map(|s| s.set_case_insensitive(fold_case)).collect());
+        self
+    }
+
+    pub fn is_case_insensitive(&self) -> bool {
+        self.fold_case
+    }
+
+    pub fn get_classification(&self) -> Classification {
+        self.classification
+    }
+
     pub fn is_block_type(&self) -> bool {
         self.block.is_some()
     }
@@ -112,59 +391,114 @@ impl State {
         self.min
     }
 
-    pub fn block_size(&self) -> Option<usize> {
-        if let Some(b) = self.get_block_states() {
-            Some(b.len())
-        } else {
-            None
-        }
+    pub fn get_identifier(&self) -> usize {
+        self.identifier
     }
 
-    fn get_block_states(&self) -> Option<&Vec<State>> {
-        if let None = self.block {
-            None
-        } else {
-            self.block.as_ref()
+    pub fn get_patterns(&self) -> &Vec<PatternType> {
+        &self.patterns
+    }
+
+    pub fn get_literal(&self) -> Option<&str> {
+        match self.patterns.as_slice() {
+            [PatternType::Literal(text)] => Some(text),
+            _ => None,
         }
     }
 
+    pub fn is_literal(&self) -> bool {
+        self.get_literal().is_some()
+    }
+
+    pub fn block_size(&self) -> Option<usize> {
+        self.get_block_states().map(|b| b.len())
+    }
+
+    pub fn get_block_states(&self) -> Option<&Vec<State>> {
+        self.block.as_ref()
+    }
+
     pub fn expand_block_states(&self) -> Option<Vec<State>> {
         Some(self.get_block_states()?.clone())
     }
 
-    /// Checks if a character works for the state
-    pub fn does_char_qualify(&self, character: char) -> bool {
-        if self.is_block_type() {
-            let block_vec_opt = self.get_block_states();
-            if let None = block_vec_opt {
-                return false;
-            }
+    /// What the state can start with. Computed once and then shared by every clone of the state.
+    pub fn first_chars(&self) -> &FirstChars {
+        self.first.get_or_init(|| self.compute_first_chars())
+    }
+
+    fn compute_first_chars(&self) -> FirstChars {
+        if self.max == Some(0) {
+            return FirstChars { leaves: Vec::new(), repetition_can_be_empty: true, can_be_empty: true };
+        }
 
-            // Otherwise, we can start checking. 
-            let block_vec = block_vec_opt.unwrap();
-            if block_vec.len() == 0 {
-                return false; // Why even bother, this is incorrect form.
+        let mut leaves = Vec::new();
+        let repetition_can_be_empty = match &self.block {
+            None => {
+                let empty_literal = self.get_literal().is_some_and(|text| text.is_empty());
+                if !empty_literal {
+                    // The leaf gets its own cache, otherwise it would keep the cache it's stored in alive.
+                    let mut leaf = self.clone();
+                    leaf.first = Arc::default();
+                    leaves.push(leaf);
+                }
+                empty_literal
             }
-            for state in block_vec.iter() {
-                let check_result = state.does_char_qualify(character);
-                if !state.allows_skip() {
-                    return check_result;
+            Some(states) if self.coalesce => {
+                for state in states {
+                    leaves.extend(state.first_chars().leaves.iter().cloned());
                 }
-                // Else, we would try the next state if it can be skipped.
-                if check_result {
-                    return true;
+                states.iter().any(|s| s.first_chars().can_be_empty)
+            }
+            Some(states) => {
+                let mut all_empty = true;
+                for state in states {
+                    let first = state.first_chars();
+                    leaves.extend(first.leaves.iter().cloned());
+                    if !first.can_be_empty {
+                        all_empty = false;
+                        break;
+                    }
                 }
+                all_empty
             }
+        };
+
+        FirstChars { leaves, repetition_can_be_empty, can_be_empty: repetition_can_be_empty || self.min == 0 }
+    }
 
-            return false;
+    /// Checks if a character works for the state. For blocks, that's whether a repetition can start with it.
+    pub fn does_char_qualify(&self, character: char) -> bool {
+        if self.is_block_type() {
+            self.first_chars().can_start_with(character)
+        } else {
+            let table = self.get_pattern_table();
+            if self.fold_case {
+                self.patterns.iter().any(|pattern| pattern.is_of_type_ignoring_case(character, self.classification, table))
+            } else {
+                self.patterns.iter().any(|pattern| pattern.is_of_type_with(character, self.classification, table))
+            }
         }
+    }
 
-        for pattern in self.patterns.iter() {
-            if pattern.is_of_type(character) {
-                return true;
+    /// How far a plain state could run at the front of the rest of the input, as the size of one unit and how many
+    /// units fit. Literals go a whole copy of the string at a time, everything else a character at a time.
+    pub fn longest_run<I: Iterator<Item = char>>(&self, rest: I) -> (usize, usize) {
+        match self.get_literal() {
+            Some(literal) => {
+                let text: Vec<char> = literal.chars().collect();
+                if text.is_empty() {
+                    return (0, self.min); // Nothing to consume, so more copies won't change anything.
+                }
+                // No need to look further than the most copies the state could take.
+                let limit = self.max.map_or(usize::MAX, |max| max.saturating_mul(text.len()));
+                let rest: Vec<char> = rest.take(limit).collect();
+                let same = |a: &char, b: &char| a == b || (self.fold_case && case_variants(*a).contains(b));
+                let copies = rest.chunks(text.len()).take_while(|chunk| chunk.len() == text.len() && chunk.iter().zip(&text).all(|(a, b)| same(a, b))).count();
+                (text.len(), copies)
             }
+            None => (1, rest.take(self.max.unwrap_or(usize::MAX)).take_while(|c| self.does_char_qualify(*c)).count()),
         }
-        false
     }
 
     /// Don't really use this function out of context. Doesn't work how you would expect it to.
@@ -194,15 +528,43 @@ impl State {
     }
 
     pub fn allows_skip(&self) -> bool {
-        if self.min == 0 {
-            true
-        } else {
-            false
+        self.min == 0
+    }
+
+    /// The fewest and most characters the state can ever take, with `None` for no upper limit.
+    pub fn length_bounds(&self) -> (usize, Option<usize>) {
+        match self.get_block_states() {
+            // A coalesce repeats whichever option it picked, so it's the widest spread over the options.
+            Some(states) if self.is_coalesce() => {
+                let options: Vec<(usize, Option<usize>)> = states.iter().map(|s| repeat_bounds(s.length_bounds(), self.min, self.max)).collect();
+                let low = options.iter().map(|o| o.0).min().unwrap_or(0);
+                let high = options.iter().try_fold(0, |high: usize, o| o.1.map(|h| high.max(h)));
+                (low, if options.is_empty() { Some(0) } else { high })
+            }
+            Some(states) => repeat_bounds(sequence_length_bounds(states), self.min, self.max),
+            None => {
+                let unit = self.get_literal().map_or(1, |text| text.chars().count());
+                repeat_bounds((unit, Some(unit)), self.min, self.max)
+            }
+        }
+    }
+
+    /// Whether the state can get away with consuming nothing at all, which is a bit broader than allows_skip for blocks.
+    pub fn can_be_empty(&self) -> bool {
+        if self.allows_skip() {
+            return true;
+        }
+
+        match self.get_block_states() {
+            None => self.get_literal().is_some_and(|text| text.is_empty()),
+            Some(states) if self.is_coalesce() => states.iter().any(|s| s.can_be_empty()),
+            Some(states) => states.iter().all(|s| s.can_be_empty()),
         }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)] // The original tests, kept as written.
 mod tests {
     use super::*;
 
@@ -212,4 +574,108 @@ mod tests {
         let block_state = State::new_block(1, Some(2), vec_states);
         assert_eq!(block_state.does_char_qualify('1'), true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn check_predicates() {
+        let vowels = PatternType::predicate("vowel", |c| "aeiou".contains(c));
+        let copy = vowels.clone();
+        assert!(copy.is_of_type('e') && !copy.is_of_type('x'));
+        assert!(format!("{:?}", copy).contains("\"vowel\""));
+
+        let state = State::new(1, None, vec![copy]);
+        let mut engine = crate::regex::engine::Engine::new("aei".to_string(), vec![state.clone()]);
+        assert!(engine.process().unwrap());
+        let mut engine = crate::regex::engine::Engine::new("abc".to_string(), vec![state]);
+        assert!(!engine.process().unwrap());
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<State>();
+    }
+
+    #[test]
+    fn check_ranges_and_sets() {
+        assert!(PatternType::Range('A', 'F').is_of_type('C'));
+        assert!(!PatternType::Range('A', 'F').is_of_type('G'));
+
+        let set = PatternType::new_set(vec![('a', 'f'), ('0', '9'), ('_', '_'), ('c', 'h')]);
+        match &set {
+            PatternType::Set(ranges) => assert_eq!(ranges, &vec![('0', '9'), ('_', '_'), ('a', 'h')]),
+            _ => unreachable!(),
+        }
+        assert!(set.is_of_type('g') && set.is_of_type('_') && set.is_of_type('0'));
+        assert!(!set.is_of_type('i') && !set.is_of_type('A'));
+
+        let not_set = PatternType::Not(Box::new(set));
+        assert!(not_set.is_of_type('A') && !not_set.is_of_type('5'));
+    }
+
+    #[test]
+    fn check_length_bounds() {
+        let bounds = |text: &str| crate::regex::builder::Builder::init_default(text.to_string()).build().unwrap().length_bounds();
+        assert_eq!(bounds("3N1\"-\"2N1\"-\"4N"), (11, Some(11)));
+        assert_eq!(bounds("1.3A.2\"ab\""), (1, Some(7)));
+        assert_eq!(bounds("2(1\"abc\",1N)"), (2, Some(6)));
+        assert_eq!(bounds("1U.L"), (1, None));
+        assert_eq!(bounds("'3N"), (0, None));
+
+        // A coalesce repeats the option it picked, so the counts multiply per option.
+        let coalesce = State::new_coalesce_block(2, Some(3), vec![State::new_literal(1, Some(1), "abc"), State::new(1, Some(2), vec![PatternType::Numeric])]);
+        assert_eq!(coalesce.length_bounds(), (2, Some(9)));
+        assert_eq!(State::new_block(0, Some(0), vec![State::new(1, None, vec![PatternType::Everything])]).length_bounds(), (0, Some(0)));
+
+        // Inputs that can't fit get turned away before the engine tries anything.
+        let mut engine = crate::regex::engine::Engine::new("1234".to_string(), vec![State::new(1, Some(3), vec![PatternType::Numeric])]);
+        assert!(!engine.process().unwrap());
+    }
+
+    #[test]
+    fn check_first_chars() {
+        let numeric = |min, max| State::new(min, max, vec![PatternType::Numeric]);
+
+        // Optional states at the front let whatever comes after them start the block too.
+        let block = State::new_block(1, Some(1), vec![numeric(0, Some(2)), State::new_literal(1, Some(1), "x"), numeric(1, Some(1))]);
+        let first = block.first_chars();
+        assert!(first.can_start_with('5') && first.can_start_with('x'));
+        assert!(!first.can_start_with('y') && !first.can_be_empty());
+        assert!(first.admits(Some('x')) && !first.admits(None));
+
+        let coalesce = State::new_coalesce_block(1, Some(1), vec![State::new(1, None, vec![PatternType::Uppercase]), block.clone()]);
+        assert!(coalesce.first_chars().can_start_with('Q') && coalesce.first_chars().can_start_with('x'));
+        assert!(!coalesce.first_chars().can_start_with('q'));
+
+        let optional = State::new_block(2, Some(2), vec![numeric(0, None), State::new_literal(1, Some(1), "")]);
+        assert!(optional.first_chars().repetition_can_be_empty() && optional.first_chars().admits(None));
+        assert!(!State::new_block(0, Some(0), vec![numeric(1, Some(1))]).does_char_qualify('1'));
+
+        // Changing the state throws away what was worked out before.
+        let folded = State::new_literal(1, Some(1), "x").set_case_insensitive(true);
+        assert!(folded.first_chars().can_start_with('X'));
+        assert!(!folded.set_case_insensitive(false).first_chars().can_start_with('X'));
+    }
+
+    #[test]
+    fn check_default_classification() {
+        // A state made by hand has to classify like one out of the Builder with default settings.
+        let built = crate::regex::builder::Builder::init_default("1U".to_string()).build().unwrap();
+        let by_hand = State::new(1, Some(1), vec![PatternType::Uppercase]);
+        assert_eq!(by_hand.get_classification(), built.get_states()[0].get_classification());
+        assert_eq!(by_hand.get_classification(), Classification::Ascii);
+        assert!(!by_hand.does_char_qualify('\u{c9}'));
+    }
+
+    #[test]
+    fn check_groups() {
+        let numeric = |min, max| State::new(min, max, vec![PatternType::Numeric]);
+        let group = State::new_group(2, Some(2), vec![State::new_option(vec![numeric(1, Some(1))]), State::new_option(vec![numeric(1, Some(1)), numeric(1, Some(1))])]);
+        let options = group.get_group_options().unwrap();
+        assert!(!options[0].is_block_type() && options[1].block_size() == Some(2));
+        assert!(State::new_block(1, Some(1), vec![numeric(1, Some(1))]).get_group_options().is_none());
+        assert!(State::new_coalesce_block(1, Some(1), vec![numeric(1, Some(1))]).get_group_options().is_none());
+
+        // 2.3 copies of 1.2N is anywhere from 2 to 6 digits, but 2.3 copies of 5N skips 11 to 14.
+        assert_eq!(numeric(1, Some(2)).fold_counts(2, Some(3)).map(|s| (s.get_min(), s.get_max())), Some((2, Some(6))));
+        assert_eq!(numeric(3, Some(3)).fold_counts(2, Some(2)).map(|s| (s.get_min(), s.get_max())), Some((6, Some(6))));
+        assert!(numeric(5, Some(5)).fold_counts(2, Some(3)).is_none());
+        assert!(State::new_coalesce_block(1, Some(1), vec![numeric(1, Some(1))]).fold_counts(2, None).is_none());
+    }
+}
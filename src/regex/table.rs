@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::helpers::{normalize_ranges, ranges_contain};
+use super::rule::PatternType;
+
+/// User-defined pattern codes, GT.M patcode style. Each code letter maps to the set of characters it accepts.
+///
+/// The text format is a trimmed down version of the GT.M pattern table file:
+///
+/// ```text
+/// PATSTART            ; optional
+/// PATTABLE SITE       ; optional, just names the table
+/// PATCODE X
+///     48-57, 65-70    ; decimal code points, or inclusive ranges of them
+/// PATCODE Y 32,45     ; values can also follow on the same line
+/// PATEND              ; optional
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PatternTable {
+    name: Option<String>,
+    codes: BTreeMap<char, Vec<(char, char)>>,
+}
+
+impl PatternTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds (or replaces) a code. The standard codes can't be redefined, and codes are case insensitive.
+    pub fn define(&mut self, code: char, ranges: Vec<(char, char)>) -> Result<(), &'static str> {
+        if !code.is_ascii_alphabetic() {
+            return Err("Pattern codes have to be a single letter.");
+        }
+        if PatternType::from_code(code).is_some() {
+            return Err("Pattern tables can't redefine the standard codes.");
+        }
+
+        self.codes.insert(code.to_ascii_uppercase(), normalize_ranges(ranges));
+        Ok(())
+    }
+
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn is_defined(&self, code: char) -> bool {
+        self.codes.contains_key(&code.to_ascii_uppercase())
+    }
+
+    /// The sorted, merged ranges a code accepts.
+    pub fn get_ranges(&self, code: char) -> Option<&Vec<(char, char)>> {
+        self.codes.get(&code.to_ascii_uppercase())
+    }
+
+    pub fn contains(&self, code: char, character: char) -> bool {
+        self.get_ranges(code).is_some_and(|ranges| ranges_contain(ranges, character))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, &'static str> {
+        let text = std::fs::read_to_string(path).map_err(|_| "Failed to read the pattern table file.")?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let mut table = Self::new();
+        let mut current: Option<(char, Vec<(char, char)>)> = None;
+
+        for raw_line in text.lines() {
+            let line = raw_line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut words = line.splitn(2, char::is_whitespace);
+            let keyword = words.next().unwrap().to_ascii_uppercase();
+            let rest = words.next().unwrap_or("").trim();
+            match keyword.as_str() {
+                "PATSTART" | "PATEND" => {}
+
+                "PATTABLE" => table.name = Some(rest.to_string()),
+
+                "PATCODE" => {
+                    if let Some((code, ranges)) = current.take() {
+                        table.finish_code(code, ranges)?;
+                    }
+
+                    let mut parts = rest.splitn(2, char::is_whitespace);
+                    let letter = parts.next().unwrap_or("");
+                    let mut letters = letter.chars();
+                    let code = match (letters.next(), letters.next()) {
+                        (Some(c), None) => c,
+                        _ => return Err("PATCODE needs a single letter."),
+                    };
+
+                    let mut ranges = Vec::new();
+                    Self::parse_values(parts.next().unwrap_or(""), &mut ranges)?;
+                    current = Some((code, ranges));
+                }
+
+                _ => match current.as_mut() {
+                    Some((_, ranges)) => Self::parse_values(line, ranges)?,
+                    None => return Err("Pattern table values have to come after a PATCODE line."),
+                },
+            }
+        }
+
+        if let Some((code, ranges)) = current.take() {
+            table.finish_code(code, ranges)?;
+        }
+        Ok(table)
+    }
+
+    fn finish_code(&mut self, code: char, ranges: Vec<(char, char)>) -> Result<(), &'static str> {
+        if ranges.is_empty() {
+            return Err("PATCODE has no characters.");
+        }
+        self.define(code, ranges)
+    }
+
+    /// Reads comma separated code points or ranges like `65-90`. A dash on its own after the last comma is a GT.M line
+    /// continuation, while one after a number is a range with no end.
+    fn parse_values(text: &str, ranges: &mut Vec<(char, char)>) -> Result<(), &'static str> {
+        let text = text.trim();
+        let text = match text.strip_suffix('-') {
+            Some(rest) if rest.trim_end().is_empty() || rest.trim_end().ends_with(',') => rest,
+            _ => text,
+        };
+        for value in text.split(',') {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+
+            let (low, high) = match value.split_once('-') {
+                Some((_, high)) if high.trim().is_empty() => return Err("Pattern table range has no end."),
+                Some((low, high)) => (Self::parse_code_point(low)?, Self::parse_code_point(high)?),
+                None => {
+                    let single = Self::parse_code_point(value)?;
+                    (single, single)
+                }
+            };
+            if low > high {
+                return Err("Pattern table range runs backwards.");
+            }
+            ranges.push((low, high));
+        }
+        Ok(())
+    }
+
+    fn parse_code_point(text: &str) -> Result<char, &'static str> {
+        let number: u32 = text.trim().parse().map_err(|_| "Invalid code point in pattern table.")?;
+        char::from_u32(number).ok_or("Invalid code point in pattern table.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::builder::Builder;
+    use crate::regex::dialect::Dialect;
+
+    const SITE_TABLE: &str = "
+PATSTART
+    PATTABLE SITE
+    PATCODE X           ; hex digits
+        48-57,-
+        65-70
+    PATCODE y 95, 45    ; underscore and dash
+PATEND
+";
+
+    #[test]
+    fn parse_table() {
+        let table = PatternTable::parse(SITE_TABLE).unwrap();
+        assert_eq!(table.get_name(), Some("SITE"));
+        assert!(table.is_defined('x') && table.is_defined('Y'));
+        assert!(table.contains('X', 'C') && table.contains('X', '7'));
+        assert!(!table.contains('X', 'G'));
+        assert_eq!(table.get_ranges('Y').unwrap(), &vec![('-', '-'), ('_', '_')]);
+
+        assert!(PatternTable::parse("65").is_err());
+        assert!(PatternTable::parse("PATCODE N 65").is_err());
+        assert!(PatternTable::parse("PATCODE XY 65").is_err());
+        assert!(PatternTable::parse("PATCODE X 70-65").is_err());
+        assert!(PatternTable::parse("PATCODE X abc").is_err());
+        assert!(PatternTable::parse("PATCODE X").is_err());
+        assert_eq!(PatternTable::parse("PATCODE X 65-").err(), Some("Pattern table range has no end."));
+        assert_eq!(PatternTable::parse("PATCODE X 48-57,65-\n  70").err(), Some("Pattern table range has no end."));
+        assert!(PatternTable::parse("PATCODE X 48-57, -\n  65").unwrap().contains('X', 'A'));
+    }
+
+    #[test]
+    fn builder_resolves_custom_codes() {
+        let table = PatternTable::parse(SITE_TABLE).unwrap();
+        let pattern = Builder::init_default("4X1Y.XN".to_string())
+            .set_dialect(Dialect::Gtm)
+            .set_pattern_table(table.clone())
+            .build()
+            .unwrap();
+        assert!(pattern.is_match("BEEF_12").unwrap());
+        assert!(pattern.is_match("00A0-").unwrap());
+        assert!(!pattern.is_match("beef_").unwrap());
+
+        // Codes still have to be defined, and only GT.M takes a table at all.
+        let undefined = Builder::init_default("1Z".to_string()).set_dialect(Dialect::Gtm).set_pattern_table(table.clone());
+        assert!(undefined.build().is_err());
+        let ansi = Builder::init_default("1N".to_string()).set_pattern_table(table);
+        assert_eq!(ansi.build().err(), Some("Pattern tables only apply to dialects with user-defined codes."));
+    }
+}
@@ -0,0 +1,9 @@
+//! Fixtures shared by the test modules.
+
+use crate::regex::builder::Builder;
+use crate::regex::pattern::Pattern;
+
+/// Builds a pattern with the default settings plus extended syntax, which every plain pattern text still parses under.
+pub fn compile(text: &str) -> Pattern {
+    Builder::init_default(text.to_string()).set_extended_syntax(true).build().unwrap()
+}